@@ -0,0 +1,16 @@
+#import bevy_pbr::mesh_view_bindings
+
+@group(1) @binding(0)
+var sky_texture: texture_cube<f32>;
+@group(1) @binding(1)
+var sky_sampler: sampler;
+
+struct FragmentInput {
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let direction = in.world_position.xyz - view.world_position.xyz;
+    return textureSample(sky_texture, sky_sampler, direction);
+}
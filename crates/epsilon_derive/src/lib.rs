@@ -0,0 +1,398 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Expr, Fields,
+    GenericArgument, Lit, LitStr, Meta, NestedMeta, Path, PathArguments, Type,
+};
+
+/// Derives `FromMapProperties` for a struct with named fields.
+///
+/// Every field is read from the entity property with the same name, parsed with `FromMapValue`.
+/// Fields can be configured with `#[map(...)]`:
+/// - `rename = "_color"` reads a different key
+/// - `default` uses `Default::default()` when the key is missing
+/// - `default = 800.0` or `default = "Vec3::ZERO"` uses a literal or an expression
+/// - `display = "..."` and `description = "..."` are written to the FGD
+/// - `kind = "target_source"` overrides the FGD property type
+///
+/// `Option<T>` fields are `None` when the key is missing, so they can't have a `default`.
+///
+/// `#[map(point_class = "light_point")]` on the struct also implements `MapEntityClass`,
+/// `solid_class` and `base_class` declare the other kinds. The class accepts
+/// `description`, `base = "A, B"`, `size = "-8 -8 -8, 8 8 8"` and `color = "0 255 0"`.
+///
+/// The generated code refers to `::epsilon::qmap`, `epsilon` names itself that with
+/// `extern crate self as epsilon`. Other crates can point it elsewhere with
+/// `#[map(crate = "::my_crate::epsilon")]` on the struct.
+#[proc_macro_derive(FromMapProperties, attributes(map))]
+pub fn derive_from_map_properties(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match from_map_properties(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct FieldOptions {
+    key: String,
+    default: Option<TokenStream2>,
+    display: Option<String>,
+    description: String,
+    kind: Option<String>,
+}
+
+#[derive(Default)]
+struct ClassOptions {
+    /// `FgdClassKind` variant and class name
+    class: Option<(TokenStream2, String)>,
+    description: String,
+    base: Vec<String>,
+    size: Option<TokenStream2>,
+    color: Option<TokenStream2>,
+    /// Path of the crate with the `qmap` module
+    root: Option<Path>,
+}
+
+fn from_map_properties(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.span(),
+                    "FromMapProperties needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "FromMapProperties can only be derived for structs",
+            ))
+        }
+    };
+
+    let class = class_options(&input.attrs)?;
+    let qmap = match &class.root {
+        Some(root) => quote! { #root::qmap },
+        None => quote! { ::epsilon::qmap },
+    };
+
+    let mut parsers: Vec<TokenStream2> = vec![];
+    let mut definitions: Vec<TokenStream2> = vec![];
+    let mut idents = vec![];
+    let mut locals = vec![];
+    for field in fields.iter() {
+        let ident = field.ident.as_ref().expect("Named fields have identifiers");
+        // Prefixed so fields can't shadow `properties` or `errors`
+        let local = format_ident!("field_{}", ident);
+        let options = field_options(field)?;
+        if options.default.is_some() && option_inner(&field.ty).is_some() {
+            return Err(Error::new(
+                field.span(),
+                "Option fields are None when the key is missing, remove `default`",
+            ));
+        }
+        let key = &options.key;
+        let parser = match option_inner(&field.ty) {
+            Some(inner) => quote! {
+                let #local = match #qmap::properties::field::<#inner>(properties, #key) {
+                    Ok(value) => Some(value),
+                    Err(err) => {
+                        errors.push(err);
+                        None
+                    }
+                };
+            },
+            None => {
+                let ty = &field.ty;
+                let missing = match &options.default {
+                    Some(default) => quote! { Some(#default) },
+                    None => quote! {{
+                        errors.push(#qmap::properties::PropertyError::Missing {
+                            key: #key.to_string(),
+                        });
+                        None
+                    }},
+                };
+                quote! {
+                    let #local: Option<#ty> =
+                        match #qmap::properties::field::<#ty>(properties, #key) {
+                            Ok(Some(value)) => Some(value),
+                            Ok(None) => #missing,
+                            Err(err) => {
+                                errors.push(err);
+                                None
+                            }
+                        };
+                }
+            }
+        };
+        parsers.push(parser);
+        definitions.push(property_definition(
+            &qmap,
+            &options,
+            option_inner(&field.ty).unwrap_or(&field.ty),
+        ));
+        idents.push(ident);
+        locals.push(local);
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let class_impl = match class.class {
+        Some((kind, class_name)) => {
+            let description = class.description;
+            let base = class.base;
+            let size = class.size.unwrap_or_else(|| quote! { None });
+            let color = class.color.unwrap_or_else(|| quote! { None });
+            quote! {
+                impl #impl_generics #qmap::properties::MapEntityClass for #name #ty_generics #where_clause {
+                    const CLASSNAME: &'static str = #class_name;
+
+                    fn fgd_class() -> #qmap::fgd::FgdClass {
+                        #qmap::fgd::FgdClass {
+                            kind: #qmap::fgd::FgdClassKind::#kind,
+                            name: #class_name.to_string(),
+                            description: #description.to_string(),
+                            base: vec![#(#base.to_string()),*],
+                            size: #size,
+                            color: #color,
+                            properties: <Self as #qmap::properties::FromMapProperties>::fgd_properties(),
+                        }
+                    }
+                }
+            }
+        }
+        None => quote! {},
+    };
+
+    Ok(quote! {
+        impl #impl_generics #qmap::properties::FromMapProperties for #name #ty_generics #where_clause {
+            fn from_map_properties(
+                properties: &::bevy::utils::HashMap<String, String>,
+            ) -> Result<Self, Vec<#qmap::properties::PropertyError>> {
+                #[allow(unused_mut)]
+                let mut errors: Vec<#qmap::properties::PropertyError> = vec![];
+                #(#parsers)*
+                if !errors.is_empty() {
+                    return Err(errors);
+                }
+                Ok(Self {
+                    #(#idents: #locals.expect("Field was parsed without errors"),)*
+                })
+            }
+
+            fn fgd_properties() -> Vec<#qmap::fgd::FgdProperty> {
+                vec![#(#definitions),*]
+            }
+        }
+
+        #class_impl
+    })
+}
+
+/// `FgdProperty` for a field, `value_type` is the field type without `Option`
+fn property_definition(
+    qmap: &TokenStream2,
+    options: &FieldOptions,
+    value_type: &Type,
+) -> TokenStream2 {
+    let key = &options.key;
+    let display = options.display.clone().unwrap_or_else(|| display_name(key));
+    let description = &options.description;
+    let kind = match &options.kind {
+        Some(kind) => quote! { #qmap::fgd::FgdPropertyKind::from_name(#kind) },
+        None => quote! { <#value_type as #qmap::properties::FromMapValue>::fgd_kind() },
+    };
+    let default = match &options.default {
+        Some(default) => quote! {{
+            let value: #value_type = #default;
+            Some(#qmap::properties::ToMapValue::to_map_value(&value))
+        }},
+        None => quote! { None },
+    };
+    quote! {
+        #qmap::fgd::FgdProperty {
+            name: #key.to_string(),
+            kind: #kind,
+            display_name: #display.to_string(),
+            default: #default,
+            description: #description.to_string(),
+        }
+    }
+}
+
+/// `_ambient_color` is shown as "Ambient color"
+fn display_name(key: &str) -> String {
+    let words = key.trim_start_matches('_').replace('_', " ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => key.to_string(),
+    }
+}
+
+fn map_options(attrs: &[Attribute]) -> Result<Vec<NestedMeta>, Error> {
+    let mut options = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("map")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => options.extend(list.nested),
+            meta => return Err(Error::new(meta.span(), "Expected #[map(...)]")),
+        }
+    }
+    Ok(options)
+}
+
+fn string(lit: &Lit) -> Result<&LitStr, Error> {
+    match lit {
+        Lit::Str(value) => Ok(value),
+        lit => Err(Error::new(lit.span(), "Expected a string")),
+    }
+}
+
+fn field_options(field: &syn::Field) -> Result<FieldOptions, Error> {
+    let mut options = FieldOptions {
+        key: field
+            .ident
+            .as_ref()
+            .map(|ident| ident.to_string())
+            .unwrap_or_default(),
+        default: None,
+        display: None,
+        description: String::new(),
+        kind: None,
+    };
+    for nested in map_options(&field.attrs)? {
+        match &nested {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default") => {
+                options.default = Some(quote! { ::std::default::Default::default() });
+            }
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("default") => {
+                options.default = Some(match &pair.lit {
+                    Lit::Str(expr) => {
+                        let expr: Expr = expr.parse()?;
+                        quote! { #expr }
+                    }
+                    lit => quote! { #lit },
+                });
+            }
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("rename") => {
+                options.key = string(&pair.lit)?.value();
+            }
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("display") => {
+                options.display = Some(string(&pair.lit)?.value());
+            }
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("description") => {
+                options.description = string(&pair.lit)?.value();
+            }
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("kind") => {
+                options.kind = Some(string(&pair.lit)?.value());
+            }
+            nested => return Err(Error::new(
+                nested.span(),
+                "Unknown option, expected `rename`, `default`, `display`, `description` or `kind`",
+            )),
+        }
+    }
+    Ok(options)
+}
+
+fn class_options(attrs: &[Attribute]) -> Result<ClassOptions, Error> {
+    let mut options = ClassOptions::default();
+    for nested in map_options(attrs)? {
+        let pair = match &nested {
+            NestedMeta::Meta(Meta::NameValue(pair)) => pair,
+            nested => return Err(Error::new(nested.span(), "Expected `option = \"value\"`")),
+        };
+        let value = string(&pair.lit)?;
+        let name = pair
+            .path
+            .get_ident()
+            .map(|ident| ident.to_string())
+            .unwrap_or_default();
+        match name.as_str() {
+            "point_class" => options.class = Some((quote! { Point }, value.value())),
+            "solid_class" => options.class = Some((quote! { Solid }, value.value())),
+            "base_class" => options.class = Some((quote! { Base }, value.value())),
+            "description" => options.description = value.value(),
+            "crate" => options.root = Some(value.parse()?),
+            "base" => {
+                options.base = value
+                    .value()
+                    .split(',')
+                    .map(|base| base.trim().to_string())
+                    .filter(|base| !base.is_empty())
+                    .collect()
+            }
+            "size" => {
+                let numbers = numbers::<f32>(value)?;
+                let vec3 = |n: &[f32]| {
+                    let [x, y, z] = [n[0], n[1], n[2]].map(Literal::f32_suffixed);
+                    quote! { ::bevy::prelude::Vec3::new(#x, #y, #z) }
+                };
+                // Like the FGD, a single size is centered on the origin
+                let (min, max) = match numbers.len() {
+                    3 => {
+                        let half: Vec<f32> = numbers.iter().map(|n| n / 2.0).collect();
+                        let min: Vec<f32> = half.iter().map(|n| -n).collect();
+                        (vec3(&min), vec3(&half))
+                    }
+                    6 => (vec3(&numbers[..3]), vec3(&numbers[3..])),
+                    _ => {
+                        return Err(Error::new(
+                            value.span(),
+                            "Expected \"x y z\" or \"x y z, x y z\"",
+                        ))
+                    }
+                };
+                options.size = Some(quote! { Some((#min, #max)) });
+            }
+            "color" => {
+                let numbers = numbers::<u8>(value)?;
+                if numbers.len() != 3 {
+                    return Err(Error::new(value.span(), "Expected \"r g b\" from 0 to 255"));
+                }
+                let [r, g, b] = [numbers[0], numbers[1], numbers[2]].map(Literal::u8_suffixed);
+                options.color = Some(quote! { Some(::bevy::prelude::Color::rgb_u8(#r, #g, #b)) });
+            }
+            _ => {
+                return Err(Error::new(
+                    pair.span(),
+                    "Unknown option, expected `point_class`, `solid_class`, `base_class`, `description`, `base`, `size`, `color` or `crate`",
+                ))
+            }
+        }
+    }
+    Ok(options)
+}
+
+fn numbers<T: std::str::FromStr>(value: &LitStr) -> Result<Vec<T>, Error> {
+    value
+        .value()
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|number| !number.is_empty())
+        .map(|number| number.parse::<T>().ok())
+        .collect::<Option<Vec<T>>>()
+        .ok_or_else(|| Error::new(value.span(), "Invalid number"))
+}
+
+/// `T` for fields of type `Option<T>`
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -2,55 +2,90 @@ use bevy::prelude::*;
 use bevy_inspector_egui::WorldInspectorPlugin;
 use bevy_rapier3d::prelude::*;
 
-use crate::{import::ImporterPlugins, qmap::QMapPlugin};
+use crate::{
+    import::ImporterPlugins,
+    qmap::{
+        fgd::Fgd,
+        logic::MapLogicPlugin,
+        registry::{MapEntityAppExt, MapEntityRegistry},
+        settings::QMapSettings,
+        QMapPlugin,
+    },
+};
 
-use self::{kinematic::kinematic_movement, light::*, player::*};
+use self::{kinematic::kinematic_movement, light::*, player::*, trigger::TriggerPlugin};
 
 pub mod hierarchy;
 pub mod kinematic;
 pub mod light;
 pub mod player;
+pub mod trigger;
 
-pub fn init() {
+/// Map loaded when no level is given on the command line
+pub const DEFAULT_LEVEL: &str = "levels/station.map";
+
+/// Asset path of the map to play
+pub struct Level(pub String);
+
+pub fn init(level: &str) {
     App::new()
+        .insert_resource(Level(level.to_string()))
         .add_plugins(DefaultPlugins)
         .add_plugins(ImporterPlugins)
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
         // .add_plugin(RapierDebugRenderPlugin::default())
+        .insert_resource(QMapSettings {
+            glass_textures: vec!["station/glass_1".to_string()],
+            ..default()
+        })
         .add_plugin(QMapPlugin)
-        .add_plugin(LightPlugin)
+        .add_plugin(EntityClassPlugin)
+        .add_plugin(TriggerPlugin)
         // .add_plugin(HierarchyVisualizerPlugin)
         .add_plugin(WorldInspectorPlugin::new())
         .add_startup_system(map_setup)
         .add_startup_system(setup)
         .add_system(mouse_capture)
-        .add_system(player_spawn)
         .add_system(player_system)
         .add_system(player_camera)
         .add_system(kinematic_movement)
         .run();
 }
 
-fn map_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+/// Spawners and FGD definitions of the game's map entities
+pub struct EntityClassPlugin;
+
+impl Plugin for EntityClassPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(MapLogicPlugin)
+            .add_plugin(LightPlugin)
+            .register_map_class(spawn_player);
+    }
+}
+
+/// Write the definitions of every registered map entity, so TrenchBroom matches the game
+pub fn export_fgd(path: &str) -> std::io::Result<()> {
+    std::fs::write(path, entity_fgd().to_string())
+}
+
+fn entity_fgd() -> Fgd {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(bevy::asset::AssetPlugin)
+        .add_plugin(QMapPlugin)
+        .add_plugin(EntityClassPlugin);
+    app.world.resource::<MapEntityRegistry>().fgd()
+}
+
+fn map_setup(mut commands: Commands, asset_server: Res<AssetServer>, level: Res<Level>) {
     commands.spawn_bundle(SceneBundle {
-        scene: asset_server.load("levels/station.map"),
+        scene: asset_server.load(&level.0),
         transform: Transform::from_xyz(0.0, 0.0, 0.0),
         ..default()
     });
 }
 
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    // Ambient light
-    commands.insert_resource(AmbientLight {
-        color: Color::Hsla {
-            hue: 0.0,
-            saturation: 0.0,
-            lightness: 1.0,
-            alpha: 1.0,
-        },
-        brightness: 1.0,
-    });
-
     // Scene
     commands
         .spawn()
@@ -77,3 +112,19 @@ fn mouse_capture(
         window.set_cursor_lock_mode(false);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::qmap::fgd::Fgd;
+
+    #[test]
+    fn entity_fgd_is_exported() {
+        let fgd = super::entity_fgd();
+        assert_eq!(Ok(&fgd), Fgd::parse(&fgd.to_string()).as_ref());
+        assert_eq!(
+            include_str!("../assets/entity.fgd"),
+            fgd.to_string(),
+            "assets/entity.fgd is out of date, run `epsilon --export-fgd`"
+        );
+    }
+}
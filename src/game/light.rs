@@ -1,48 +1,49 @@
 use bevy::prelude::*;
 
-use crate::qmap::component::MapPointEntity;
+use crate::qmap::{
+    component::MapPointEntity, properties::FromMapProperties, registry::MapEntityAppExt,
+};
 
 pub struct LightPlugin;
 
 impl Plugin for LightPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_system(light_builder);
+        app.register_map_class(spawn_point_light);
     }
 }
 
-fn light_builder(
-    mut commands: Commands,
-    query: Query<(Entity, &MapPointEntity), Added<MapPointEntity>>,
+#[derive(FromMapProperties)]
+#[map(
+    point_class = "light_point",
+    description = "Point light",
+    size = "-8 -8 -8, 8 8 8",
+    color = "255 255 0"
+)]
+struct LightPoint {
+    #[map(default = 800.0, description = "Light intensity")]
+    intensity: f32,
+    #[map(default = 240.0, description = "Light range in map units")]
+    range: f32,
+}
+
+fn spawn_point_light(
+    commands: &mut Commands,
+    entity: Entity,
+    map_point_entity: &MapPointEntity,
+    light: LightPoint,
 ) {
-    for (entity, map_point_entity) in query.iter() {
-        match map_point_entity.name.as_str() {
-            "light_point" => {
-                commands.entity(entity).with_children(|builder| {
-                    builder
-                        .spawn()
-                        .insert(Name::new("point light"))
-                        .insert_bundle(PointLightBundle {
-                            point_light: PointLight {
-                                intensity: map_point_entity
-                                    .properties
-                                    .get("intensity")
-                                    .unwrap_or(&"800.0".to_string())
-                                    .parse::<f32>()
-                                    .unwrap_or_default(),
-                                range: map_point_entity
-                                    .properties
-                                    .get("range")
-                                    .unwrap_or(&"15.0".to_string())
-                                    .parse::<f32>()
-                                    .unwrap_or_default(),
-                                color: Color::hsl(0.50, 0.15, 0.7),
-                                ..default()
-                            },
-                            ..default()
-                        });
-                });
-            }
-            _ => (),
-        }
-    }
+    commands.entity(entity).with_children(|builder| {
+        builder
+            .spawn()
+            .insert(Name::new("point light"))
+            .insert_bundle(PointLightBundle {
+                point_light: PointLight {
+                    intensity: light.intensity,
+                    range: map_point_entity.conversion.to_world_length(light.range),
+                    color: Color::hsl(0.50, 0.15, 0.7),
+                    ..default()
+                },
+                ..default()
+            });
+    });
 }
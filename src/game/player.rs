@@ -3,7 +3,7 @@ use std::f32::consts::PI;
 use bevy::{input::mouse::MouseMotion, prelude::*};
 use bevy_rapier3d::prelude::*;
 
-use crate::qmap::component::MapPointEntity;
+use crate::qmap::{component::MapPointEntity, properties::FromMapProperties};
 
 use super::kinematic::*;
 
@@ -67,49 +67,51 @@ pub fn player_camera(
     }
 }
 
-pub fn player_spawn(
-    mut commands: Commands,
-    query: Query<(Entity, &MapPointEntity), Added<MapPointEntity>>,
-) {
-    for (entity, map_point_entity) in query.iter() {
-        match map_point_entity.name.as_str() {
-            "info_player_start" => {
-                commands.entity(entity).with_children(|builder| {
-                    let radius = 0.3;
-                    let mut kinematic = KinematicBundle::default();
-                    kinematic.collider = Collider::ball(radius);
-                    kinematic.transform = TransformBundle::default();
+#[derive(FromMapProperties)]
+#[map(
+    point_class = "info_player_start",
+    description = "Player 1 start",
+    size = "-8 -8 -8, 8 8 8",
+    color = "0 255 0"
+)]
+pub struct PlayerStart {}
 
-                    builder
-                        .spawn()
-                        .insert(Name::new("player"))
-                        .insert_bundle(PlayerBundle {
-                            kinematic,
-                            ..default()
-                        })
-                        .insert(KinematicProperties {
-                            speed: 8.0,
-                            acceleration: 4.0,
-                            friction: 4.0,
-                            turning_lerp: 20.0,
-                        })
-                        .insert(KinematicInput::default())
-                        .with_children(|build| {
-                            build.spawn().insert(Name::new("camera")).insert_bundle(
-                                Camera3dBundle {
-                                    projection: bevy::render::camera::Projection::Perspective(
-                                        PerspectiveProjection {
-                                            fov: f32::to_radians(80.0),
-                                            ..default()
-                                        },
-                                    ),
-                                    ..default()
-                                },
-                            );
-                        });
-                });
-            }
-            _ => (),
-        }
-    }
+pub fn spawn_player(commands: &mut Commands, entity: Entity, _: &MapPointEntity, _: PlayerStart) {
+    commands.entity(entity).with_children(|builder| {
+        let radius = 0.3;
+        let kinematic = KinematicBundle {
+            collider: Collider::ball(radius),
+            transform: TransformBundle::default(),
+            ..default()
+        };
+
+        builder
+            .spawn()
+            .insert(Name::new("player"))
+            .insert_bundle(PlayerBundle {
+                kinematic,
+                ..default()
+            })
+            .insert(KinematicProperties {
+                speed: 8.0,
+                acceleration: 4.0,
+                friction: 4.0,
+                turning_lerp: 20.0,
+            })
+            .insert(KinematicInput::default())
+            .with_children(|build| {
+                build
+                    .spawn()
+                    .insert(Name::new("camera"))
+                    .insert_bundle(Camera3dBundle {
+                        projection: bevy::render::camera::Projection::Perspective(
+                            PerspectiveProjection {
+                                fov: f32::to_radians(80.0),
+                                ..default()
+                            },
+                        ),
+                        ..default()
+                    });
+            });
+    });
 }
@@ -0,0 +1,204 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier3d::prelude::*;
+
+use crate::qmap::{component::MapBrushEntity, logic::FireOutput};
+
+pub struct TriggerPlugin;
+
+impl Plugin for TriggerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TriggerEntered>()
+            .add_event::<TriggerExited>()
+            .add_event::<FireOutput>()
+            .add_system(trigger_events)
+            .add_system(trigger_outputs.after(trigger_events));
+    }
+}
+
+/// Sent when an entity starts touching a `trigger_*` brush entity
+#[derive(Clone, Debug)]
+pub struct TriggerEntered {
+    /// The brush entity carrying the map properties
+    pub trigger: Entity,
+    /// The entity that touched the trigger
+    pub other: Entity,
+    pub name: String,
+    pub properties: HashMap<String, String>,
+}
+
+/// Sent when an entity stops touching a `trigger_*` brush entity
+#[derive(Clone, Debug)]
+pub struct TriggerExited {
+    /// The brush entity carrying the map properties
+    pub trigger: Entity,
+    /// The entity that left the trigger
+    pub other: Entity,
+    pub name: String,
+    pub properties: HashMap<String, String>,
+}
+
+fn trigger_events(
+    mut collision_events: EventReader<CollisionEvent>,
+    parent_query: Query<&Parent>,
+    trigger_query: Query<&MapBrushEntity>,
+    mut entered_events: EventWriter<TriggerEntered>,
+    mut exited_events: EventWriter<TriggerExited>,
+) {
+    // Brush colliders are children of the brush entity
+    let find_trigger = |collider: Entity| {
+        let parent = parent_query.get(collider).ok()?.get();
+        let trigger = trigger_query.get(parent).ok()?;
+        trigger.is_trigger().then_some((parent, trigger))
+    };
+
+    for event in collision_events.iter() {
+        let (a, b, started) = match event {
+            CollisionEvent::Started(a, b, _) => (*a, *b, true),
+            CollisionEvent::Stopped(a, b, _) => (*a, *b, false),
+        };
+        for (collider, other) in [(a, b), (b, a)] {
+            let (trigger, map_entity) = match find_trigger(collider) {
+                Some(found) => found,
+                None => continue,
+            };
+            let name = map_entity.name.clone();
+            let properties = map_entity.properties.clone();
+            if started {
+                entered_events.send(TriggerEntered {
+                    trigger,
+                    other,
+                    name,
+                    properties,
+                });
+            } else {
+                exited_events.send(TriggerExited {
+                    trigger,
+                    other,
+                    name,
+                    properties,
+                });
+            }
+        }
+    }
+}
+
+/// Fires `OnStartTouch` and `OnTrigger` when touched, `OnEndTouch` when left.
+/// `MapLogicPlugin` sends the inputs connected to them.
+fn trigger_outputs(
+    mut entered_events: EventReader<TriggerEntered>,
+    mut exited_events: EventReader<TriggerExited>,
+    mut outputs: EventWriter<FireOutput>,
+) {
+    for event in entered_events.iter() {
+        for output in ["OnStartTouch", "OnTrigger"] {
+            outputs.send(FireOutput::new(event.trigger, output, Some(event.other)));
+        }
+    }
+    for event in exited_events.iter() {
+        outputs.send(FireOutput::new(
+            event.trigger,
+            "OnEndTouch",
+            Some(event.other),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use bevy_rapier3d::{prelude::*, rapier::geometry::CollisionEventFlags};
+
+    use super::{TriggerEntered, TriggerExited, TriggerPlugin};
+    use crate::qmap::{component::MapBrushEntity, logic::FireOutput};
+
+    fn trigger_app() -> (App, Entity, Entity, Entity) {
+        let mut app = App::new();
+        app.add_event::<CollisionEvent>().add_plugin(TriggerPlugin);
+
+        let brush = app.world.spawn().id();
+        let trigger = app
+            .world
+            .spawn()
+            .insert(MapBrushEntity {
+                name: "trigger_once".to_string(),
+                properties: [("target".to_string(), "door1".to_string())].into(),
+            })
+            .push_children(&[brush])
+            .id();
+        let player = app.world.spawn().id();
+        (app, trigger, brush, player)
+    }
+
+    #[test]
+    fn enter_and_exit() {
+        let (mut app, trigger, brush, player) = trigger_app();
+
+        app.world
+            .resource_mut::<Events<CollisionEvent>>()
+            .send(CollisionEvent::Started(
+                player,
+                brush,
+                CollisionEventFlags::SENSOR,
+            ));
+        app.update();
+
+        let events = app.world.resource::<Events<TriggerEntered>>();
+        let entered: Vec<_> = events.get_reader().iter(events).cloned().collect();
+        assert_eq!(1, entered.len());
+        assert_eq!(trigger, entered[0].trigger);
+        assert_eq!(player, entered[0].other);
+        assert_eq!("trigger_once", entered[0].name);
+        assert_eq!(
+            Some(&"door1".to_string()),
+            entered[0].properties.get("target")
+        );
+        let events = app.world.resource::<Events<FireOutput>>();
+        let outputs: Vec<_> = events
+            .get_reader()
+            .iter(events)
+            .map(|event| (event.entity, event.output.clone(), event.activator))
+            .collect();
+        assert_eq!(
+            vec![
+                (trigger, "OnStartTouch".to_string(), Some(player)),
+                (trigger, "OnTrigger".to_string(), Some(player))
+            ],
+            outputs
+        );
+
+        app.world
+            .resource_mut::<Events<CollisionEvent>>()
+            .send(CollisionEvent::Stopped(
+                brush,
+                player,
+                CollisionEventFlags::SENSOR,
+            ));
+        app.update();
+
+        let events = app.world.resource::<Events<TriggerExited>>();
+        let exited: Vec<_> = events.get_reader().iter(events).cloned().collect();
+        assert_eq!(1, exited.len());
+        assert_eq!(trigger, exited[0].trigger);
+        assert_eq!(player, exited[0].other);
+    }
+
+    #[test]
+    fn non_trigger_brush_entity() {
+        let (mut app, trigger, brush, player) = trigger_app();
+        app.world.entity_mut(trigger).insert(MapBrushEntity {
+            name: "func_wall".to_string(),
+            ..default()
+        });
+
+        app.world
+            .resource_mut::<Events<CollisionEvent>>()
+            .send(CollisionEvent::Started(
+                player,
+                brush,
+                CollisionEventFlags::empty(),
+            ));
+        app.update();
+
+        assert!(app.world.resource::<Events<TriggerEntered>>().is_empty());
+    }
+}
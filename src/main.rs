@@ -1,8 +1,32 @@
+// Lets `FromMapProperties` derives refer to `::epsilon` from inside the crate too
+extern crate self as epsilon;
+
 pub mod game;
 pub mod import;
 pub mod qmap;
 pub mod util;
 
+const USAGE: &str = "Usage: epsilon [LEVEL]\n       epsilon --export-fgd [PATH]";
+
 fn main() {
-    game::init();
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("--export-fgd") => {
+            let path = args
+                .next()
+                .unwrap_or_else(|| "assets/entity.fgd".to_string());
+            if let Err(err) = game::export_fgd(&path) {
+                eprintln!("Could not write {path}: {err}");
+                std::process::exit(1);
+            }
+            println!("Wrote {path}");
+        }
+        Some("--help" | "-h") => println!("{USAGE}"),
+        Some(flag) if flag.starts_with('-') => {
+            eprintln!("Unknown option {flag}\n{USAGE}");
+            std::process::exit(2);
+        }
+        Some(level) => game::init(level),
+        None => game::init(game::DEFAULT_LEVEL),
+    }
 }
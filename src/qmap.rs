@@ -1,20 +1,75 @@
-use self::{component::MapPointEntity, loader::QMapLoader, types::MAP_SCALE};
-use bevy::prelude::*;
+use self::{
+    animation::AnimatedTexturePlugin,
+    component::{MapBrushEntity, MapPointEntity, SkySurface},
+    loader::{MapSidecar, MapSidecarLoader, QMapLoader},
+    material::{MaterialDefinition, MaterialDefinitionLoader},
+    properties::MapEntityClass,
+    registry::{map_entity_dispatcher, MapEntityAppExt, MapEntityRegistry},
+    settings::{MapConversion, QMapSettings},
+    sky::{sky_material_spawner, MapSky, SkyMaterial},
+    target::{target_linker, KillTargets, MapEntityNames, TargetName, Targets},
+    wad::{Wad, WadLoader},
+    worldspawn::{apply_worldspawn, worldspawn_loader, MapWorldspawn, Worldspawn},
+};
+use bevy::{prelude::*, reflect::FromReflect};
 use bevy_rapier3d::prelude::*;
 
+pub mod animation;
 mod build;
 pub mod component;
+mod csg;
+pub mod error;
+pub mod fgd;
 mod loader;
+pub mod logic;
+pub mod material;
+pub mod properties;
+pub mod registry;
+pub mod settings;
+pub mod sky;
+pub mod target;
+#[cfg(test)]
+mod test_util;
+mod texture;
 mod types;
+pub mod wad;
+pub mod worldspawn;
 
 pub struct QMapPlugin;
 
 impl Plugin for QMapPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.init_asset_loader::<QMapLoader>()
+        app.init_resource::<QMapSettings>()
+            .init_resource::<MapEntityRegistry>()
+            .init_resource::<MapEntityNames>()
+            .init_asset_loader::<QMapLoader>()
+            .add_asset::<MapSidecar>()
+            .init_asset_loader::<MapSidecarLoader>()
+            .add_asset::<MaterialDefinition>()
+            .init_asset_loader::<MaterialDefinitionLoader>()
+            .add_asset::<Wad>()
+            .init_asset_loader::<WadLoader>()
+            .add_plugin(MaterialPlugin::<SkyMaterial>::default())
+            .add_plugin(AnimatedTexturePlugin)
             .register_type::<Hull>()
+            .register_type::<CompoundHull>()
+            .register_type::<TrimeshHull>()
             .register_type::<MapPointEntity>()
-            .add_system(collision_spawner);
+            .register_type::<MapBrushEntity>()
+            .register_type::<SkySurface>()
+            .register_type::<MapConversion>()
+            .register_type::<MapWorldspawn>()
+            .register_type::<MapSky>()
+            .register_type::<TargetName>()
+            .register_type::<Targets>()
+            .register_type::<KillTargets>()
+            .register_fgd_class(Worldspawn::fgd_class())
+            .add_system(collision_spawner)
+            .add_system(map_entity_dispatcher)
+            .add_system(target_linker)
+            .add_system(sky_material_spawner)
+            .add_system(worldspawn_loader)
+            .add_system(apply_worldspawn.after(worldspawn_loader));
     }
 }
 
@@ -24,25 +79,443 @@ impl Plugin for QMapPlugin {
 #[reflect(Component)]
 pub struct Hull {
     pub points: Vec<Vec3>,
+    pub sensor: bool,
+    pub location: BrushLocation,
 }
 
+/// Hulls of all brushes in an entity, built into a single compound collider
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct CompoundHull {
+    pub hulls: Vec<Vec<Vec3>>,
+    /// Where each of the `hulls` came from
+    pub locations: Vec<BrushLocation>,
+}
+
+/// Triangles of the visible brush faces in an entity, built into a triangle mesh collider
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct TrimeshHull {
+    pub vertices: Vec<Vec3>,
+    /// Three indices into `vertices` per triangle
+    pub indices: Vec<u32>,
+    pub entity: usize,
+}
+
+/// Index of the entity and the brush within it in the map file, used in error messages
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Reflect, FromReflect)]
+pub struct BrushLocation {
+    pub entity: usize,
+    pub brush: usize,
+}
+
+impl std::fmt::Display for BrushLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "entity {}, brush {}", self.entity, self.brush)
+    }
+}
+
+/// Added to hulls that couldn't be turned into a collider, so they aren't retried every frame
+#[derive(Component)]
+pub struct InvalidHull;
+
+impl Hull {
+    /// `None` for degenerate brushes, e.g. a brush squashed flat while editing
+    pub fn collider(&self) -> Option<Collider> {
+        convex_collider(&self.points)
+    }
+}
+
+impl CompoundHull {
+    /// Leaves out degenerate brushes, `None` if there's nothing left
+    pub fn collider(&self) -> Option<Collider> {
+        let shapes: Vec<(Vec3, Quat, Collider)> = self
+            .hulls
+            .iter()
+            .filter_map(|points| convex_collider(points))
+            .map(|collider| (Vec3::ZERO, Quat::IDENTITY, collider))
+            .collect();
+        if shapes.is_empty() {
+            return None;
+        }
+        Some(Collider::compound(shapes))
+    }
+
+    pub fn degenerate_brushes(&self) -> impl Iterator<Item = (BrushLocation, &[Vec3])> {
+        self.locations
+            .iter()
+            .zip(self.hulls.iter())
+            .filter(|(_, points)| !has_volume(points))
+            .map(|(location, points)| (*location, &points[..]))
+    }
+}
+
+impl TrimeshHull {
+    pub fn collider(&self) -> Option<Collider> {
+        if self.indices.len() < 3 {
+            return None;
+        }
+        let indices = self
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+        Some(Collider::trimesh(self.vertices.clone(), indices))
+    }
+}
+
+fn convex_collider(points: &[Vec3]) -> Option<Collider> {
+    if !has_volume(points) {
+        return None;
+    }
+    Collider::convex_hull(points)
+}
+
+/// Whether there are at least four points that don't lie on the same plane
+fn has_volume(points: &[Vec3]) -> bool {
+    const EPSILON: f32 = 0.001;
+    let first = match points.first() {
+        Some(first) => *first,
+        None => return false,
+    };
+    let farthest = |distance: &dyn Fn(Vec3) -> f32| {
+        points
+            .iter()
+            .map(|point| (*point, distance(*point)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .filter(|(_, distance)| *distance > EPSILON)
+            .map(|(point, _)| point)
+    };
+    let second = match farthest(&|point| point.distance(first)) {
+        Some(point) => point,
+        None => return false,
+    };
+    let line = (second - first).normalize();
+    let third = match farthest(&|point| (point - first).reject_from_normalized(line).length()) {
+        Some(point) => point,
+        None => return false,
+    };
+    let normal = line.cross(third - first).normalize();
+    farthest(&|point| (point - first).dot(normal).abs()).is_some()
+}
+
+/// Hulls that haven't been turned into colliders yet
+type PendingHull = (Without<Collider>, Without<InvalidHull>);
+
+#[allow(clippy::too_many_arguments)]
 fn collision_spawner(
-    mut query: Query<Entity, (With<Hull>, Without<Collider>)>,
-    hull_query: Query<&Hull>,
+    hull_query: Query<(Entity, &Hull, Option<&Parent>), PendingHull>,
+    compound_query: Query<(Entity, &CompoundHull, Option<&Parent>), PendingHull>,
+    trimesh_query: Query<(Entity, &TrimeshHull, Option<&Parent>), PendingHull>,
+    brush_entity_query: Query<&MapBrushEntity>,
+    transform_query: Query<&Transform>,
+    parent_query: Query<&Parent>,
+    conversion_query: Query<&MapConversion>,
     mut commands: Commands,
 ) {
-    for entity in query.iter_mut() {
-        let hull = hull_query.get(entity).unwrap();
-        let collider =
-            Collider::convex_hull(&hull.points[..]).expect("Failed to create collider for brush");
-        commands.entity(entity).insert(collider);
+    let in_trigger = |parent: Option<&Parent>| {
+        parent
+            .and_then(|parent| brush_entity_query.get(parent.get()).ok())
+            .map(|brush_entity| brush_entity.is_trigger())
+            .unwrap_or(false)
+    };
+    let report_degenerate = |entity: Entity, location: BrushLocation, point: Option<Vec3>| {
+        let conversion = map_conversion(entity, &parent_query, &conversion_query);
+        let position = point
+            .map(|point| format!(" near {}", conversion.to_map(point)))
+            .unwrap_or_default();
+        warn!("Degenerate brush at {location}{position} has no volume, skipping its collider");
+    };
+
+    let mut colliders: Vec<(Entity, Option<Collider>, bool)> = vec![];
+    for (entity, hull, parent) in hull_query.iter() {
+        let collider = hull.collider();
+        if collider.is_none() {
+            // Hull points are relative to the brush origin
+            let origin = transform_query
+                .get(entity)
+                .map(|transform| transform.translation)
+                .unwrap_or_default();
+            let point = hull.points.first().map(|point| *point + origin);
+            report_degenerate(entity, hull.location, point);
+        }
+        colliders.push((entity, collider, hull.sensor || in_trigger(parent)));
+    }
+    for (entity, hull, parent) in compound_query.iter() {
+        for (location, points) in hull.degenerate_brushes() {
+            report_degenerate(entity, location, points.first().copied());
+        }
+        colliders.push((entity, hull.collider(), in_trigger(parent)));
+    }
+    for (entity, hull, parent) in trimesh_query.iter() {
+        let collider = hull.collider();
+        if collider.is_none() {
+            warn!(
+                "Triangle mesh of entity {} has no triangles, skipping its collider",
+                hull.entity
+            );
+        }
+        colliders.push((entity, collider, in_trigger(parent)));
     }
+
+    for (entity, collider, sensor) in colliders {
+        let mut entity = commands.entity(entity);
+        match collider {
+            Some(collider) => {
+                entity.insert(collider);
+                if sensor {
+                    entity.insert(Sensor);
+                }
+            }
+            None => {
+                entity.insert(InvalidHull);
+            }
+        }
+    }
+}
+
+/// Conversion of the map the entity belongs to, found on the map root
+pub fn map_conversion(
+    entity: Entity,
+    parent_query: &Query<&Parent>,
+    conversion_query: &Query<&MapConversion>,
+) -> MapConversion {
+    map_component(entity, parent_query, conversion_query)
+        .copied()
+        .unwrap_or_default()
 }
 
-pub fn convert_coords(map_point: Vec3) -> Vec3 {
-    Vec3 {
-        x: map_point.x,
-        y: map_point.z,
-        z: -map_point.y,
-    } * MAP_SCALE
+/// The closest `T` on the entity or its ancestors, map settings live on the map root
+pub fn map_component<'a, T: Component>(
+    entity: Entity,
+    parent_query: &Query<&Parent>,
+    query: &'a Query<&T>,
+) -> Option<&'a T> {
+    let mut current = Some(entity);
+    while let Some(entity) = current {
+        if let Ok(component) = query.get(entity) {
+            return Some(component);
+        }
+        current = parent_query.get(entity).ok().map(|parent| parent.get());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+    use shalrath::repr::Map;
+
+    use super::{
+        build::{compound_hull, hull_points, is_trigger_brush, trimesh_hull, MapSurfaces},
+        collision_spawner,
+        csg::cull_hidden_faces,
+        has_volume,
+        loader::{convert_face_coords, faces_from_brush},
+        settings::MapConversion,
+        types::Face,
+        BrushLocation, CompoundHull, Hull, InvalidHull, PendingHull,
+    };
+
+    /// Solid colliders of the worldspawn for each mode: convex per brush, compound and trimesh
+    fn mode_colliders(source: &str) -> [Vec<Collider>; 3] {
+        let map = source.parse::<Map>().unwrap();
+        let brushes: Vec<_> = map.0[0]
+            .brushes
+            .iter()
+            .map(|brush| faces_from_brush(brush, &HashMap::new()))
+            .filter(|faces| !is_trigger_brush(faces))
+            .collect();
+        // Same order as the loader: cull in map coordinates, then convert
+        let convert = |brushes: Vec<Vec<Face>>| -> Vec<Vec<Face>> {
+            brushes
+                .iter()
+                .map(|faces| {
+                    faces
+                        .iter()
+                        .map(|face| convert_face_coords(face, &MapConversion::default()))
+                        .collect()
+                })
+                .collect()
+        };
+        let visible = convert(cull_hidden_faces(&brushes, &MapSurfaces::default()));
+        let brushes = convert(brushes);
+
+        let convex = brushes
+            .iter()
+            .map(|faces| {
+                Hull {
+                    points: hull_points(faces),
+                    ..default()
+                }
+                .collider()
+                .unwrap()
+            })
+            .collect();
+        let compound = compound_hull(
+            brushes
+                .iter()
+                .map(|faces| (BrushLocation::default(), &faces[..])),
+        )
+        .collider()
+        .unwrap();
+        let trimesh = trimesh_hull(0, visible.iter().map(|faces| &faces[..]))
+            .collider()
+            .unwrap();
+        [convex, vec![compound], vec![trimesh]]
+    }
+
+    fn cast_ray(colliders: &[Collider], origin: Vec3, dir: Vec3) -> Option<f32> {
+        colliders
+            .iter()
+            .filter_map(|collider| {
+                collider.cast_ray(Vec3::ZERO, Quat::IDENTITY, origin, dir, 1000.0, true)
+            })
+            .reduce(f32::min)
+    }
+
+    fn assert_modes_agree(source: &str) {
+        let [convex, compound, trimesh] = mode_colliders(source);
+        let (min, max) = convex
+            .iter()
+            .flat_map(|collider| {
+                collider
+                    .as_convex_polyhedron()
+                    .unwrap()
+                    .raw
+                    .points()
+                    .to_vec()
+            })
+            .fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), point| (min.min(point.into()), max.max(point.into())),
+            );
+        let mut hits = 0;
+        for axis in 0..3 {
+            for sign in [1.0, -1.0] {
+                // Tilted so the rays aren't parallel to the brush faces
+                let mut dir = Vec3::new(0.1, 0.15, 0.12);
+                dir[axis] = sign;
+                let dir = dir.normalize();
+                // Grid of rays crossing the map from outside its bounds
+                for i in 0..=10 {
+                    for j in 0..=10 {
+                        let t = Vec3::new(i as f32, j as f32, (i + j) as f32 / 2.0) / 10.0;
+                        let mut origin = min - 0.25 + (max - min + 0.5) * t;
+                        origin[axis] = if sign > 0.0 {
+                            min[axis] - 1.0
+                        } else {
+                            max[axis] + 1.0
+                        };
+                        let expected = cast_ray(&convex, origin, dir);
+                        for (mode, colliders) in [("compound", &compound), ("trimesh", &trimesh)] {
+                            let toi = cast_ray(colliders, origin, dir);
+                            match (expected, toi) {
+                                (Some(expected), Some(toi)) => assert!(
+                                    (expected - toi).abs() < 0.01,
+                                    "{mode} ray from {origin} towards {dir}: {expected} != {toi}"
+                                ),
+                                _ => assert_eq!(
+                                    expected, toi,
+                                    "{mode} ray from {origin} towards {dir}"
+                                ),
+                            }
+                        }
+                        hits += expected.is_some() as usize;
+                    }
+                }
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn collider_modes_agree() {
+        assert_modes_agree(include_str!("../assets/levels/tests/csg_partial.map"));
+        assert_modes_agree(include_str!("../assets/levels/tests/tool_textures.map"));
+        assert_modes_agree(include_str!("../assets/levels/in_hull.map"));
+    }
+
+    #[test]
+    fn compound_contains_points() {
+        // Trimesh colliders are hollow, so only the solid modes are compared here
+        let [convex, compound, _] =
+            mode_colliders(include_str!("../assets/levels/tests/csg_partial.map"));
+        let contains = |colliders: &[Collider], point: Vec3| {
+            colliders
+                .iter()
+                .any(|collider| collider.contains_point(Vec3::ZERO, Quat::IDENTITY, point))
+        };
+        for (point, inside) in [
+            (Vec3::new(4.0, 2.0, -4.0), true),
+            (Vec3::new(3.0, 5.0, -3.0), true),
+            (Vec3::new(6.0, 5.0, -6.0), false),
+            (Vec3::new(-1.0, 2.0, -4.0), false),
+        ] {
+            assert_eq!(inside, contains(&convex, point), "{point}");
+            assert_eq!(inside, contains(&compound, point), "{point}");
+        }
+    }
+
+    #[test]
+    fn degenerate_points() {
+        let square = [Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0), Vec3::Y];
+        assert!(!has_volume(&[]));
+        assert!(!has_volume(&[Vec3::ONE; 8]));
+        assert!(!has_volume(&[
+            Vec3::ZERO,
+            Vec3::X,
+            Vec3::X * 2.0,
+            Vec3::X * 3.0
+        ]));
+        assert!(!has_volume(&square));
+        assert!(has_volume(&[&square[..], &[Vec3::Z]].concat()));
+    }
+
+    #[test]
+    fn degenerate_hull_is_marked_once() {
+        let mut app = App::new();
+        app.add_system(collision_spawner);
+        let flat = app
+            .world
+            .spawn()
+            .insert(Hull {
+                points: vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::new(1.0, 1.0, 0.0)],
+                ..default()
+            })
+            .id();
+        let cube = app
+            .world
+            .spawn()
+            .insert(Hull {
+                points: (0..8)
+                    .map(|i| Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32))
+                    .collect(),
+                ..default()
+            })
+            .id();
+        let compound = app
+            .world
+            .spawn()
+            .insert(CompoundHull {
+                hulls: vec![vec![Vec3::ZERO, Vec3::X]],
+                locations: vec![BrushLocation::default()],
+            })
+            .id();
+        app.update();
+
+        assert!(app.world.get::<InvalidHull>(flat).is_some());
+        assert!(app.world.get::<Collider>(flat).is_none());
+        assert!(app.world.get::<Collider>(cube).is_some());
+        assert!(app.world.get::<InvalidHull>(compound).is_some());
+
+        app.update();
+        let mut pending = app
+            .world
+            .query_filtered::<Entity, (With<Hull>, PendingHull)>();
+        assert_eq!(0, pending.iter(&app.world).count());
+    }
 }
@@ -0,0 +1,341 @@
+use bevy::{asset::LoadContext, prelude::*};
+
+use super::{
+    logic::{MapInput, MapLogicAppExt},
+    map_component,
+    texture::texture_image_path,
+    wad::MapWads,
+};
+
+/// Frames per second in Quake
+const DEFAULT_FRAME_RATE: f32 = 5.0;
+
+/// Cycles animated brush textures. Faces textured with any frame of a Quake style sequence,
+/// `+0name` to `+9name`, show all frames in turn, or the alternate `+aname` to `+jname` frames
+/// while the entity has `AlternateTextures(true)`, e.g. a pressed button.
+pub struct AnimatedTexturePlugin;
+
+impl Plugin for AnimatedTexturePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AnimatedTextureSettings>()
+            .register_type::<AnimatedTexture>()
+            .register_type::<AlternateTextures>()
+            .add_system(animate_textures)
+            .register_global_map_input("ToggleTexture", toggle_texture);
+    }
+}
+
+pub struct AnimatedTextureSettings {
+    /// Frames per second, 0 shows the first frame only
+    pub frame_rate: f32,
+}
+
+impl Default for AnimatedTextureSettings {
+    fn default() -> Self {
+        AnimatedTextureSettings {
+            frame_rate: DEFAULT_FRAME_RATE,
+        }
+    }
+}
+
+/// Frames of an animated surface, its material shows the current one
+#[derive(Default, Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct AnimatedTexture {
+    pub frames: Vec<Handle<Image>>,
+    /// One `_emission` map per frame, or empty if not every frame has one
+    pub emission_frames: Vec<Handle<Image>>,
+    pub alternate_frames: Vec<Handle<Image>>,
+    pub alternate_emission_frames: Vec<Handle<Image>>,
+    /// The material's own emission map, shown while the frames have none
+    pub emissive_texture: Option<Handle<Image>>,
+}
+
+impl AnimatedTexture {
+    /// Base color and emission frames to show, the alternate set only exists for some textures
+    fn frames(&self, alternate: bool) -> (&[Handle<Image>], &[Handle<Image>]) {
+        match alternate && !self.alternate_frames.is_empty() {
+            true => (&self.alternate_frames, &self.alternate_emission_frames),
+            false => (&self.frames, &self.emission_frames),
+        }
+    }
+}
+
+/// Switches the animated textures of a map entity and its children to the alternate frames.
+/// Map entities switch with the `ToggleTexture` input.
+#[derive(Default, Component, Clone, Copy, Debug, Reflect)]
+#[reflect(Component)]
+pub struct AlternateTextures(pub bool);
+
+fn toggle_texture(world: &mut World, input: &MapInput) {
+    let alternate = world
+        .get::<AlternateTextures>(input.target)
+        .map_or(false, |alternate| alternate.0);
+    world
+        .entity_mut(input.target)
+        .insert(AlternateTextures(!alternate));
+}
+
+/// Image paths of a frame sequence
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameSet {
+    pub base_color: Vec<String>,
+    /// Empty unless every frame has an `_emission` map
+    pub emission: Vec<String>,
+}
+
+impl FrameSet {
+    fn handles(paths: &[String], load_context: &LoadContext) -> Vec<Handle<Image>> {
+        paths
+            .iter()
+            .map(|path| load_context.get_handle(path.as_str()))
+            .collect()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &String> {
+        self.base_color.iter().chain(self.emission.iter())
+    }
+}
+
+/// Frames of an animated face texture, read before building the map
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextureAnimation {
+    pub frames: FrameSet,
+    pub alternate: FrameSet,
+}
+
+impl TextureAnimation {
+    /// Finds the frames of `texture` if it's part of a sequence with more than one frame.
+    /// Frames are looked up in the map's WADs before the PNGs, like face textures.
+    pub fn load(texture: &str, wads: &MapWads, load_context: &LoadContext) -> Option<Self> {
+        let (directory, _, name) = split_animated_texture(texture)?;
+        let load_frames = |frames: std::ops::RangeInclusive<char>| {
+            let mut set = FrameSet::default();
+            for frame in frames {
+                let texture = format!("{directory}+{frame}{name}");
+                let path = match wads.image_path(&texture) {
+                    Some(path) => path,
+                    None => {
+                        let path = texture_image_path(&texture);
+                        if !load_context.asset_io().is_file(path.as_ref()) {
+                            break;
+                        }
+                        path
+                    }
+                };
+                set.base_color.push(path);
+                set.emission
+                    .push(texture_image_path(&format!("{texture}_emission")));
+            }
+            if !set
+                .emission
+                .iter()
+                .all(|path| load_context.asset_io().is_file(path.as_ref()))
+            {
+                set.emission.clear();
+            }
+            set
+        };
+        let animation = TextureAnimation {
+            frames: load_frames('0'..='9'),
+            alternate: load_frames('a'..='j'),
+        };
+        (animation.frames.base_color.len() + animation.alternate.base_color.len() > 1)
+            .then_some(animation)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &String> {
+        self.frames.paths().chain(self.alternate.paths())
+    }
+
+    /// `emissive_texture` is the emission map of the surface's material
+    pub fn component(
+        &self,
+        emissive_texture: Option<&str>,
+        load_context: &LoadContext,
+    ) -> AnimatedTexture {
+        AnimatedTexture {
+            frames: FrameSet::handles(&self.frames.base_color, load_context),
+            emission_frames: FrameSet::handles(&self.frames.emission, load_context),
+            alternate_frames: FrameSet::handles(&self.alternate.base_color, load_context),
+            alternate_emission_frames: FrameSet::handles(&self.alternate.emission, load_context),
+            emissive_texture: emissive_texture.map(|path| load_context.get_handle(path)),
+        }
+    }
+}
+
+/// Splits an animated texture like `station/+0sign` into `station/`, the frame `0` and `sign`
+pub fn split_animated_texture(texture: &str) -> Option<(&str, char, &str)> {
+    let (directory, name) = texture.split_at(texture.rfind('/').map_or(0, |index| index + 1));
+    let mut chars = name.strip_prefix('+')?.chars();
+    let frame = chars.next()?.to_ascii_lowercase();
+    let name = chars.as_str();
+    let is_frame = frame.is_ascii_digit() || ('a'..='j').contains(&frame);
+    (is_frame && !name.is_empty()).then_some((directory, frame, name))
+}
+
+/// Show the current frame of every animated surface
+pub fn animate_textures(
+    time: Res<Time>,
+    settings: Res<AnimatedTextureSettings>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    surface_query: Query<(Entity, &AnimatedTexture, &Handle<StandardMaterial>)>,
+    parent_query: Query<&Parent>,
+    alternate_query: Query<&AlternateTextures>,
+) {
+    let frame = (time.seconds_since_startup() * settings.frame_rate.max(0.0) as f64) as usize;
+    for (entity, texture, material) in surface_query.iter() {
+        let alternate = map_component(entity, &parent_query, &alternate_query)
+            .map_or(false, |alternate| alternate.0);
+        let (frames, emission_frames) = texture.frames(alternate);
+        if frames.is_empty() {
+            continue;
+        }
+        let index = frame % frames.len();
+        let base_color = Some(frames[index].clone());
+        let emission = match emission_frames.get(index) {
+            Some(emission) => Some(emission.clone()),
+            None => texture.emissive_texture.clone(),
+        };
+
+        // Only touch materials that change, modified materials are sent to the GPU again
+        let current = match materials.get(material) {
+            Some(current) => current,
+            None => continue,
+        };
+        if current.base_color_texture == base_color && current.emissive_texture == emission {
+            continue;
+        }
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color_texture = base_color;
+            material.emissive_texture = emission;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{asset::AssetPlugin, prelude::*};
+
+    use super::{
+        animate_textures, split_animated_texture, AlternateTextures, AnimatedTexture,
+        AnimatedTexturePlugin, AnimatedTextureSettings,
+    };
+    use crate::qmap::{
+        logic::{FireOutput, MapLogicPlugin},
+        target::MapEntityNames,
+        test_util::brush,
+    };
+
+    #[test]
+    fn animated_texture_names() {
+        assert_eq!(
+            Some(("station/", '0', "sign")),
+            split_animated_texture("station/+0sign")
+        );
+        assert_eq!(Some(("", 'a', "sign")), split_animated_texture("+Asign"));
+        assert_eq!(None, split_animated_texture("station/sign+0"));
+        assert_eq!(None, split_animated_texture("+ksign"));
+        assert_eq!(None, split_animated_texture("+0"));
+    }
+
+    #[test]
+    fn cycle_frames() {
+        let mut app = App::new();
+        app.add_plugin(AssetPlugin)
+            .add_asset::<Image>()
+            .add_asset::<StandardMaterial>()
+            .insert_resource(AnimatedTextureSettings { frame_rate: 10.0 })
+            .add_system(animate_textures);
+        let mut time = Time::default();
+        let start = time.startup();
+        time.update_with_instant(start);
+        app.insert_resource(time);
+
+        let mut images = app.world.resource_mut::<Assets<Image>>();
+        let frames: Vec<Handle<Image>> = (0..3).map(|_| images.add(Image::default())).collect();
+        let alternate_frames = vec![images.add(Image::default())];
+        let emission_frames: Vec<Handle<Image>> =
+            (0..3).map(|_| images.add(Image::default())).collect();
+        let emissive_texture = images.add(Image::default());
+        let material = app
+            .world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial::default());
+        let surface = app
+            .world
+            .spawn()
+            .insert(AnimatedTexture {
+                frames: frames.clone(),
+                emission_frames: emission_frames.clone(),
+                alternate_frames: alternate_frames.clone(),
+                emissive_texture: Some(emissive_texture.clone()),
+                ..default()
+            })
+            .insert(material.clone())
+            .id();
+        let button = app.world.spawn().push_children(&[surface]).id();
+
+        let update = |app: &mut App, seconds: f32| {
+            app.world
+                .resource_mut::<Time>()
+                .update_with_instant(start + Duration::from_secs_f32(seconds));
+            app.update();
+            let material = app
+                .world
+                .resource::<Assets<StandardMaterial>>()
+                .get(&material)
+                .unwrap();
+            (
+                material.base_color_texture.clone().unwrap(),
+                material.emissive_texture.clone(),
+            )
+        };
+        assert_eq!(
+            (frames[0].clone(), Some(emission_frames[0].clone())),
+            update(&mut app, 0.05)
+        );
+        assert_eq!(
+            (frames[1].clone(), Some(emission_frames[1].clone())),
+            update(&mut app, 0.15)
+        );
+        assert_eq!(frames[0], update(&mut app, 0.35).0);
+
+        // Pressed, the alternate set has no emission frames so the material's own map is back
+        app.world.entity_mut(button).insert(AlternateTextures(true));
+        assert_eq!(
+            (alternate_frames[0].clone(), Some(emissive_texture.clone())),
+            update(&mut app, 0.45)
+        );
+    }
+
+    #[test]
+    fn toggle_texture() {
+        let mut app = App::new();
+        app.add_plugin(AssetPlugin)
+            .add_asset::<Image>()
+            .add_asset::<StandardMaterial>()
+            .insert_resource(Time::default())
+            .init_resource::<MapEntityNames>()
+            .add_plugin(MapLogicPlugin)
+            .add_plugin(AnimatedTexturePlugin);
+        let button = brush(
+            &mut app,
+            "func_button",
+            &[("OnPressed", "!self,ToggleTexture")],
+        );
+        app.update();
+
+        for alternate in [true, false] {
+            app.world
+                .send_event(FireOutput::new(button, "OnPressed", None));
+            app.update();
+            assert_eq!(
+                alternate,
+                app.world.get::<AlternateTextures>(button).unwrap().0
+            );
+        }
+    }
+}
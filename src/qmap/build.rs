@@ -1,112 +1,472 @@
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
 
 use bevy::{
-    asset::{AssetPath, LoadContext, LoadedAsset},
+    asset::{LoadContext, LoadedAsset},
     prelude::*,
     render::mesh::{Indices, PrimitiveTopology},
 };
 
-use super::{component::MapPointEntity, types::*, Hull};
+use super::{
+    animation::AnimatedTexture,
+    component::{MapBrushEntity, MapPointEntity, SkySurface},
+    error::BrushError,
+    material::{texture_material, TextureImageHandles, TextureMaterial},
+    settings::ColliderMode,
+    types::*,
+    BrushLocation, CompoundHull, Hull, TrimeshHull,
+};
 
-pub fn build_brush<'a>(
-    builder: &mut WorldChildBuilder,
-    load_context: &'a mut LoadContext,
-    mesh_counter: &mut u16,
-    faces: Vec<Face>,
-) {
-    let origin = faces.first().unwrap().vertices.first().unwrap().position;
-    let mut hull: Vec<Vec3> = vec![];
+/// TrenchBroom tool textures that are handled by the builder instead of being rendered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToolTexture {
+    /// Not rendered, still solid
+    NoDraw,
+    /// Not rendered, used on hidden sides of brushes
+    Skip,
+    /// Invisible collision
+    Clip,
+    /// Invisible sensor volume
+    Trigger,
+    /// Not rendered, marked with `SkySurface` so the sky can be drawn there
+    Sky,
+}
 
-    let mut children: Vec<Entity> = vec![];
+const TOOL_TEXTURES: [(&str, ToolTexture); 5] = [
+    ("nodraw", ToolTexture::NoDraw),
+    ("skip", ToolTexture::Skip),
+    ("clip", ToolTexture::Clip),
+    ("trigger", ToolTexture::Trigger),
+    ("sky", ToolTexture::Sky),
+];
 
-    for face in faces.iter().map(|face| face.offset_to_origin(origin)) {
-        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+impl ToolTexture {
+    /// Matches on the texture name without its directory, e.g. `tools/clip`.
+    /// Like in Quake, textures starting with `sky` are sky textures too, but only without a
+    /// directory (WAD textures) or in a `sky` or `tools` directory, so `station/skylight` isn't.
+    pub fn from_texture(texture: &str) -> Option<Self> {
+        let texture = texture.to_lowercase();
+        let (directory, name) = match texture.rsplit_once('/') {
+            Some((directory, name)) => (Some(directory), name),
+            None => (None, texture.as_str()),
+        };
+        let quake_sky = directory
+            .and_then(|directory| directory.rsplit('/').next())
+            .map_or(true, |directory| matches!(directory, "sky" | "tools"));
+        TOOL_TEXTURES
+            .iter()
+            .find(|(tool_name, tool)| match tool {
+                ToolTexture::Sky if quake_sky => name.starts_with(tool_name),
+                _ => name == *tool_name,
+            })
+            .map(|(_, tool)| *tool)
+    }
 
-        let (positions, uvs, normals) = face.as_tuples();
+    /// Whether brushes made of this texture hide faces of the brushes they touch
+    pub fn occludes(&self) -> bool {
+        !matches!(self, ToolTexture::Clip | ToolTexture::Trigger)
+    }
+}
 
-        let tri_count = positions.len() - 2;
-        let mut indices: Vec<u16> = Vec::with_capacity(tri_count * 3);
-        for i in 0..tri_count {
-            indices.push(0);
-            indices.push(i as u16 + 1);
-            indices.push(i as u16 + 2);
-        }
+/// Brushes made only of trigger faces become sensors
+pub fn is_trigger_brush(faces: &[Face]) -> bool {
+    !faces.is_empty()
+        && faces
+            .iter()
+            .all(|face| ToolTexture::from_texture(&face.texture) == Some(ToolTexture::Trigger))
+}
 
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-
-        mesh.set_indices(Some(Indices::U16(indices)));
-        mesh.generate_tangents()
-            .expect("Could not generate tangents for brush");
-
-        let mesh =
-            load_context.set_labeled_asset(&format!("mesh/{mesh_counter}"), LoadedAsset::new(mesh));
-        let material = load_material(load_context, format!("textures/{}", face.texture));
-
-        children.push(
-            builder
-                .spawn()
-                .insert(Name::new("face"))
-                .insert_bundle(PbrBundle {
-                    mesh,
-                    material,
-                    ..default()
-                })
-                .id(),
-        );
+fn is_rendered(texture: &str) -> bool {
+    matches!(
+        ToolTexture::from_texture(texture),
+        None | Some(ToolTexture::Sky)
+    )
+}
 
-        for vertex in face.vertices.iter() {
-            // Add vertex to hull if it doesn't already exist
-            if !hull
-                .iter()
-                .any(|point| point.abs_diff_eq(vertex.position, 0.01))
-            {
-                hull.push(vertex.position);
-            }
+/// Meshes of a brush that are not merged, relative to the brush origin
+pub struct BrushMeshes {
+    pub origin: Vec3,
+    pub meshes: Vec<(Mesh, String)>,
+}
+
+/// Builds the meshes of a brush before anything is spawned, so a failing brush leaves nothing behind.
+/// Meshes are only made for the `visible_faces`,
+/// in merged mode they are added to `batch` instead of getting their own meshes.
+pub fn brush_meshes(
+    surfaces: &MapSurfaces,
+    faces: &[Face],
+    visible_faces: &[Face],
+    batch: Option<&mut MeshBatch>,
+) -> Result<BrushMeshes, BrushError> {
+    let origin = faces
+        .iter()
+        .find_map(|face| face.vertices.first())
+        .ok_or(BrushError::NoVertices)?
+        .position;
+
+    let mut meshes: Vec<(Mesh, String)> = vec![];
+
+    // Blended faces are sorted by distance, they keep their own meshes in merged mode
+    for face in visible_faces
+        .iter()
+        .filter(|face| is_rendered(&face.texture))
+        .filter(|face| batch.is_none() || surfaces.is_blended(&face.texture))
+        .map(|face| face.offset_to_origin(origin))
+    {
+        if let Some(mut mesh) = face_mesh(&face) {
+            mesh.generate_tangents()?;
+            meshes.push((mesh, face.texture.clone()));
         }
+    }
 
-        *mesh_counter += 1;
+    if let Some(batch) = batch {
+        for face in visible_faces
+            .iter()
+            .filter(|face| is_rendered(&face.texture) && !surfaces.is_blended(&face.texture))
+        {
+            batch.add_face(face);
+        }
     }
 
-    builder
-        .spawn()
+    Ok(BrushMeshes { origin, meshes })
+}
+
+/// Spawns a brush of a brush entity with its `meshes`,
+/// with its own hull in `ConvexPerBrush` mode or for trigger brushes.
+pub fn build_brush(
+    builder: &mut WorldChildBuilder,
+    load_context: &mut LoadContext,
+    surfaces: &mut MapSurfaces,
+    collider_mode: ColliderMode,
+    location: BrushLocation,
+    faces: Vec<Face>,
+    meshes: BrushMeshes,
+) {
+    let BrushMeshes { origin, meshes } = meshes;
+    let children: Vec<Entity> = meshes
+        .into_iter()
+        .map(|(mesh, texture)| {
+            spawn_surface(builder, load_context, surfaces, "face", &texture, mesh)
+        })
+        .collect();
+
+    let mut brush = builder.spawn();
+    brush
         .insert(Name::new("brush"))
         .insert_bundle(TransformBundle::from(Transform::from_xyz(
             origin.x, origin.y, origin.z,
         )))
         .insert_bundle(VisibilityBundle::default())
-        .insert(Hull { points: hull })
         .push_children(&children);
+    let sensor = is_trigger_brush(&faces);
+    if collider_mode == ColliderMode::ConvexPerBrush || sensor {
+        let offset_faces: Vec<Face> = faces
+            .iter()
+            .map(|face| face.offset_to_origin(origin))
+            .collect();
+        brush.insert(Hull {
+            points: hull_points(&offset_faces),
+            sensor,
+            location,
+        });
+    }
 }
 
-fn load_material<'a>(load_context: &'a mut LoadContext, path: String) -> Handle<StandardMaterial> {
-    let base_color_path = format!("{}.png", path.as_str().to_owned());
-    let base_color_path = AssetPath::new_ref(Path::new(&base_color_path), None);
-    let base_color_texture: Option<Handle<Image>> =
-        Some(load_context.get_handle(base_color_path.clone()));
-
-    let material = LoadedAsset::new(StandardMaterial {
-        base_color: Color::Rgba {
-            red: 1.0,
-            green: 1.0,
-            blue: 1.0,
-            alpha: 1.0,
-        },
-        base_color_texture,
-        metallic: 0.0,
-        reflectance: 0.0,
-        perceptual_roughness: 1.0,
-        unlit: false,
+/// Unique vertex positions of a brush
+pub fn hull_points(faces: &[Face]) -> Vec<Vec3> {
+    let mut hull: Vec<Vec3> = vec![];
+    for vertex in faces.iter().flat_map(|face| face.vertices.iter()) {
+        // Add vertex to hull if it doesn't already exist
+        if !hull
+            .iter()
+            .any(|point| point.abs_diff_eq(vertex.position, 0.01))
+        {
+            hull.push(vertex.position);
+        }
+    }
+    hull
+}
+
+/// Spawns the shared collider of an entity's brushes for the `Compound` and `Trimesh` modes.
+/// Trigger brushes are left out, they keep their own sensor hulls.
+pub fn build_entity_collider(
+    builder: &mut WorldChildBuilder,
+    collider_mode: ColliderMode,
+    entity_index: usize,
+    brushes: &[(Vec<Face>, Vec<Face>)],
+) {
+    let solid = brushes
+        .iter()
+        .enumerate()
+        .filter(|(_, (faces, _))| !is_trigger_brush(faces));
+    let mut collider = match collider_mode {
+        ColliderMode::ConvexPerBrush => return,
+        ColliderMode::Compound => {
+            let hull = compound_hull(solid.map(|(brush, (faces, _))| {
+                let location = BrushLocation {
+                    entity: entity_index,
+                    brush,
+                };
+                (location, &faces[..])
+            }));
+            if hull.hulls.is_empty() {
+                return;
+            }
+            let mut collider = builder.spawn();
+            collider.insert(hull);
+            collider
+        }
+        ColliderMode::Trimesh => {
+            let hull = trimesh_hull(
+                entity_index,
+                solid.map(|(_, (_, visible_faces))| &visible_faces[..]),
+            );
+            if hull.indices.is_empty() {
+                return;
+            }
+            let mut collider = builder.spawn();
+            collider.insert(hull);
+            collider
+        }
+    };
+    collider
+        .insert(Name::new("collider"))
+        .insert_bundle(TransformBundle::default());
+}
+
+pub fn compound_hull<'a>(
+    brushes: impl Iterator<Item = (BrushLocation, &'a [Face])>,
+) -> CompoundHull {
+    let mut hull = CompoundHull::default();
+    for (location, faces) in brushes {
+        let points = hull_points(faces);
+        if !points.is_empty() {
+            hull.hulls.push(points);
+            hull.locations.push(location);
+        }
+    }
+    hull
+}
+
+pub fn trimesh_hull<'a>(
+    entity_index: usize,
+    brushes: impl Iterator<Item = &'a [Face]>,
+) -> TrimeshHull {
+    let mut hull = TrimeshHull {
+        entity: entity_index,
         ..default()
-    })
-    .with_dependency(base_color_path);
+    };
+    for face in brushes.flatten() {
+        if face.vertices.len() < 3 {
+            continue;
+        }
+        let first = hull.vertices.len() as u32;
+        for i in 0..face.vertices.len() as u32 - 2 {
+            hull.indices.extend([first, first + i + 1, first + i + 2]);
+        }
+        hull.vertices
+            .extend(face.vertices.iter().map(|vertex| vertex.position));
+    }
+    hull
+}
+
+/// Mesh for a single face, `None` for faces that don't contribute to the brush shape
+pub fn face_mesh(face: &Face) -> Option<Mesh> {
+    let (positions, uvs, normals) = face.as_tuples();
+
+    if positions.len() < 3 {
+        return None;
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+    let tri_count = positions.len() - 2;
+    let mut indices: Vec<u16> = Vec::with_capacity(tri_count * 3);
+    for i in 0..tri_count {
+        indices.push(0);
+        indices.push(i as u16 + 1);
+        indices.push(i as u16 + 2);
+    }
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+
+    mesh.set_indices(Some(Indices::U16(indices)));
+
+    Some(mesh)
+}
 
-    load_context.set_labeled_asset(&path, material)
+/// Collects faces of multiple brushes into one mesh per texture
+#[derive(Default)]
+pub struct MeshBatch {
+    surfaces: BTreeMap<String, Surface>,
+}
+
+#[derive(Default)]
+struct Surface {
+    positions: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBatch {
+    pub fn add_face(&mut self, face: &Face) {
+        if face.vertices.len() < 3 {
+            return;
+        }
+        let surface = self.surfaces.entry(face.texture.clone()).or_default();
+        let (positions, uvs, normals) = face.as_tuples();
+
+        let first = surface.positions.len() as u32;
+        for i in 0..positions.len() as u32 - 2 {
+            surface.indices.push(first);
+            surface.indices.push(first + i + 1);
+            surface.indices.push(first + i + 2);
+        }
+
+        surface.positions.extend(positions);
+        surface.uvs.extend(uvs);
+        surface.normals.extend(normals);
+    }
+
+    pub fn meshes(self) -> Vec<(String, Mesh)> {
+        self.surfaces
+            .into_iter()
+            .map(|(texture, surface)| {
+                let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+                mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, surface.positions);
+                mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, surface.uvs);
+                mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, surface.normals);
+                mesh.set_indices(Some(Indices::U32(surface.indices)));
+                (texture, mesh)
+            })
+            .collect()
+    }
+
+    pub fn build(
+        self,
+        builder: &mut WorldChildBuilder,
+        load_context: &mut LoadContext,
+        surfaces: &mut MapSurfaces,
+    ) {
+        for (texture, mut mesh) in self.meshes() {
+            if let Err(err) = mesh.generate_tangents() {
+                warn!("Could not generate tangents for merged {texture} mesh: {err}");
+            }
+            spawn_surface(builder, load_context, surfaces, &texture, &texture, mesh);
+        }
+    }
+}
+
+/// Spawn a rendered mesh, or an invisible `SkySurface` for sky textures
+fn spawn_surface(
+    builder: &mut WorldChildBuilder,
+    load_context: &mut LoadContext,
+    surfaces: &mut MapSurfaces,
+    name: &str,
+    texture: &str,
+    mesh: Mesh,
+) -> Entity {
+    let mesh = load_context.set_labeled_asset(
+        &format!("mesh/{}", surfaces.mesh_counter),
+        LoadedAsset::new(mesh),
+    );
+    surfaces.mesh_counter += 1;
+
+    if ToolTexture::from_texture(texture) == Some(ToolTexture::Sky) {
+        return builder
+            .spawn()
+            .insert(Name::new("sky"))
+            .insert(SkySurface)
+            .insert(mesh)
+            .insert_bundle(SpatialBundle::default())
+            .id();
+    }
+
+    let (material, animation) = surfaces.material(load_context, texture);
+    let mut surface = builder.spawn();
+    surface
+        .insert(Name::new(name.to_string()))
+        .insert_bundle(PbrBundle {
+            mesh,
+            material,
+            ..default()
+        });
+    if let Some(animation) = animation {
+        surface.insert(animation);
+    }
+    surface.id()
+}
+
+/// Mesh labels and materials shared by every surface of a map
+#[derive(Default)]
+pub struct MapSurfaces {
+    mesh_counter: usize,
+    /// Material of every texture, read before building
+    materials: HashMap<String, TextureMaterial>,
+    /// One material asset per texture
+    handles: HashMap<String, Handle<StandardMaterial>>,
+    /// Labeled images per texture, shared by its animated and regular materials
+    images: HashMap<String, TextureImageHandles>,
+}
+
+impl MapSurfaces {
+    pub fn new(materials: HashMap<String, TextureMaterial>) -> Self {
+        MapSurfaces {
+            materials,
+            ..default()
+        }
+    }
+
+    fn is_blended(&self, texture: &str) -> bool {
+        self.materials
+            .get(texture)
+            .map_or(false, TextureMaterial::is_blended)
+    }
+
+    /// Textures without a material, like tool textures, count as opaque
+    pub fn is_opaque(&self, texture: &str) -> bool {
+        self.materials
+            .get(texture)
+            .map_or(true, TextureMaterial::is_opaque)
+    }
+
+    /// Animated surfaces get their own material, so entities can switch to the alternate frames
+    fn material(
+        &mut self,
+        load_context: &mut LoadContext,
+        texture: &str,
+    ) -> (Handle<StandardMaterial>, Option<AnimatedTexture>) {
+        let default = TextureMaterial::default();
+        let material = self.materials.get(texture).unwrap_or(&default);
+        let images = self
+            .images
+            .entry(texture.to_string())
+            .or_insert_with(|| material.register_images(load_context, texture));
+        if let Some(animation) = &material.animation {
+            let label = format!("textures/{texture}/{}", self.mesh_counter);
+            let asset = texture_material(load_context, texture, material, images);
+            let handle = load_context.set_labeled_asset(&label, asset);
+            let emissive_texture = material.definition.emissive_texture.as_deref();
+            return (
+                handle,
+                Some(animation.component(emissive_texture, load_context)),
+            );
+        }
+
+        if let Some(handle) = self.handles.get(texture) {
+            return (handle.clone(), None);
+        }
+        let asset = texture_material(load_context, texture, material, images);
+        let handle = load_context.set_labeled_asset(&format!("textures/{texture}"), asset);
+        self.handles.insert(texture.to_string(), handle.clone());
+        (handle, None)
+    }
 }
 
 pub fn build_point_entity(builder: &mut WorldChildBuilder, entity: MapPointEntity) {
-    let transform = entity.transform.clone();
+    let transform = entity.transform;
     builder
         .spawn()
         .insert(Name::new(entity.name.clone()))
@@ -116,3 +476,72 @@ pub fn build_point_entity(builder: &mut WorldChildBuilder, entity: MapPointEntit
             ..default()
         });
 }
+
+pub fn build_brush_entity(
+    builder: &mut WorldChildBuilder,
+    entity: MapBrushEntity,
+    build_brushes: impl FnOnce(&mut WorldChildBuilder),
+) {
+    builder
+        .spawn()
+        .insert(Name::new(entity.name.clone()))
+        .insert(entity)
+        .insert_bundle(SpatialBundle::default())
+        .with_children(build_brushes);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use shalrath::repr::Map;
+
+    use super::{is_trigger_brush, ToolTexture};
+    use crate::qmap::loader::faces_from_brush;
+
+    #[test]
+    fn tool_texture_names() {
+        assert_eq!(Some(ToolTexture::Clip), ToolTexture::from_texture("clip"));
+        assert_eq!(
+            Some(ToolTexture::Clip),
+            ToolTexture::from_texture("tools/CLIP")
+        );
+        assert_eq!(
+            Some(ToolTexture::NoDraw),
+            ToolTexture::from_texture("common/nodraw")
+        );
+        assert_eq!(Some(ToolTexture::Skip), ToolTexture::from_texture("skip"));
+        assert_eq!(
+            Some(ToolTexture::Trigger),
+            ToolTexture::from_texture("tools/trigger")
+        );
+        assert_eq!(Some(ToolTexture::Sky), ToolTexture::from_texture("sky4"));
+        assert_eq!(
+            Some(ToolTexture::Sky),
+            ToolTexture::from_texture("tools/skybox")
+        );
+        assert_eq!(
+            Some(ToolTexture::Sky),
+            ToolTexture::from_texture("common/sky")
+        );
+        // Only Quake style textures are sky because of their prefix
+        assert_eq!(None, ToolTexture::from_texture("station/skylight_1"));
+        assert_eq!(None, ToolTexture::from_texture("station/wall_1"));
+        assert_eq!(None, ToolTexture::from_texture("tools/clipboard"));
+    }
+
+    #[test]
+    fn trigger_brush() {
+        let map = include_str!("../../assets/levels/tests/tool_textures.map")
+            .parse::<Map>()
+            .unwrap();
+        let brushes: Vec<_> = map.0[0]
+            .brushes
+            .iter()
+            .map(|brush| faces_from_brush(brush, &HashMap::new()))
+            .collect();
+        assert!(!is_trigger_brush(&brushes[0]));
+        assert!(!is_trigger_brush(&brushes[1]));
+        assert!(is_trigger_brush(&brushes[2]));
+    }
+}
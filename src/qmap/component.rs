@@ -1,74 +1,420 @@
-use std::collections::HashMap;
+use bevy::{prelude::*, utils::HashMap};
+use shalrath::repr::{Entity as QMapEntity, Properties};
 
-use bevy::prelude::*;
-use shalrath::repr::Properties;
+use super::{
+    error::{PropertyError, QMapError},
+    properties::FromMapProperties,
+    settings::MapConversion,
+};
 
 #[derive(Default, Component, Clone, Reflect)]
 #[reflect(Component)]
 pub struct MapPointEntity {
     pub name: String,
     pub transform: Transform,
-    #[reflect(ignore)]
     pub properties: HashMap<String, String>,
+    /// Keys that are unknown or have a mistyped value according to the FGD
+    pub invalid_properties: Vec<String>,
+    /// Converts properties in map units, like a light range, to world units
+    pub conversion: MapConversion,
 }
 
-impl MapPointEntity {
-    pub fn from_properties(props: &Properties) -> Option<Self> {
-        if props.len() == 0 {
+/// Solid entity other than worldspawn (func_door, func_wall, ...).
+/// Its brushes are spawned as children so the entity can be handled as one unit.
+#[derive(Default, Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct MapBrushEntity {
+    pub name: String,
+    pub properties: HashMap<String, String>,
+}
+
+impl MapBrushEntity {
+    /// Returns `None` for point entities and worldspawn, whose brushes are children of the map root
+    pub fn from_entity(entity: &QMapEntity) -> Option<Self> {
+        if entity.properties.is_empty() || entity.brushes.is_empty() {
             return None;
         }
-        let mut properties: HashMap<String, String> = HashMap::new();
-        for property in props.iter() {
-            properties.insert(property.key.clone(), property.value.clone());
+        let properties = properties_to_map(&entity.properties);
+        let name = classname(&properties);
+        if name == "worldspawn" {
+            return None;
         }
-        let name = match properties.get("classname") {
-            Some(value) => value,
-            None => "missing_entity",
+        Some(MapBrushEntity { name, properties })
+    }
+
+    /// Read the properties into a typed struct, see `FromMapProperties`
+    pub fn parse_properties<T: FromMapProperties>(&self) -> Result<T, Vec<PropertyError>> {
+        T::from_map_properties(&self.properties)
+    }
+
+    /// `trigger_*` entities get sensor colliders
+    pub fn is_trigger(&self) -> bool {
+        self.name.starts_with("trigger_")
+    }
+}
+
+impl MapPointEntity {
+    /// Read the properties into a typed struct, see `FromMapProperties`
+    pub fn parse_properties<T: FromMapProperties>(&self) -> Result<T, Vec<PropertyError>> {
+        T::from_map_properties(&self.properties)
+    }
+
+    /// `transform` is in world coordinates, converted with `conversion`
+    pub fn from_properties(
+        index: usize,
+        props: &Properties,
+        conversion: MapConversion,
+    ) -> Result<Option<Self>, QMapError> {
+        if props.is_empty() {
+            return Ok(None);
         }
-        .to_string();
-        let translation = parse_position(match properties.get("origin") {
+        let properties = properties_to_map(props);
+        let name = classname(&properties);
+        let invalid_property = |key: &str, value: &str| QMapError::InvalidProperty {
+            entity: index,
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let origin = match properties.get("origin") {
             Some(value) => value,
             None => "0 0 0",
-        });
-        let rotation = parse_angle(match properties.get("angle") {
-            Some(value) => value,
-            None => "0",
-        });
+        };
+        let translation =
+            parse_position(origin).ok_or_else(|| invalid_property("origin", origin))?;
+        let rotation = parse_rotation(&name, &properties, conversion)
+            .map_err(|key| invalid_property(key, &properties[key]))?;
         let transform = Transform {
-            translation,
+            translation: conversion.to_world(translation),
             rotation,
             ..default()
         };
-        Some(MapPointEntity {
+        Ok(Some(MapPointEntity {
             name,
             transform,
             properties,
-        })
+            conversion,
+            ..default()
+        }))
+    }
+}
+
+pub fn properties_to_map(props: &Properties) -> HashMap<String, String> {
+    let mut properties: HashMap<String, String> = HashMap::new();
+    for property in props.iter() {
+        properties.insert(property.key.clone(), property.value.clone());
     }
+    properties
 }
 
-pub fn parse_position(value: &str) -> Vec3 {
+fn classname(properties: &HashMap<String, String>) -> String {
+    match properties.get("classname") {
+        Some(value) => value,
+        None => "missing_entity",
+    }
+    .to_string()
+}
+
+pub fn parse_position(value: &str) -> Option<Vec3> {
     let position: Vec<&str> = value.split_ascii_whitespace().collect();
     if position.len() == 3 {
-        Vec3 {
-            x: position[0].parse::<f32>().expect("Invalid position value"),
-            y: position[1].parse::<f32>().expect("Invalid position value"),
-            z: position[2].parse::<f32>().expect("Invalid position value"),
-        }
+        Some(Vec3 {
+            x: position[0].parse::<f32>().ok()?,
+            y: position[1].parse::<f32>().ok()?,
+            z: position[2].parse::<f32>().ok()?,
+        })
     } else {
-        Vec3::ZERO
+        None
     }
 }
 
-pub fn parse_angle(value: &str) -> Quat {
-    let angle = value.parse::<f32>().expect("Invalid angle value") - 90.0;
-    let angle = angle.to_radians();
-    // TODO: check if this and/or player camera is wrong
-    Quat::from_rotation_y(angle)
+/// Orientation from `angles`, `mangle` or `angle`, the first one the entity has.
+/// The error is the key with an invalid value.
+pub fn parse_rotation(
+    classname: &str,
+    properties: &HashMap<String, String>,
+    conversion: MapConversion,
+) -> Result<Quat, &'static str> {
+    let (pitch, yaw, roll) = if let Some(value) = properties.get("angles") {
+        let [pitch, yaw, roll] = parse_euler(value).ok_or("angles")?;
+        (pitch, yaw, roll)
+    } else if let Some(value) = properties.get("mangle") {
+        let [first, second, roll] = parse_euler(value).ok_or("mangle")?;
+        // Quake lights use `yaw pitch roll` with the pitch going up, like their spotlight target
+        match classname.starts_with("light") {
+            true => (-second, first, roll),
+            false => (first, second, roll),
+        }
+    } else if let Some(value) = properties.get("angle") {
+        let (pitch, yaw) = parse_angle(value).ok_or("angle")?;
+        (pitch, yaw, 0.0)
+    } else {
+        (0.0, 0.0, 0.0)
+    };
+    Ok(rotation_from_angles(pitch, yaw, roll, conversion))
+}
+
+/// Pitch and yaw of the `angle` key in degrees, -1 and -2 point up and down
+pub fn parse_angle(value: &str) -> Option<(f32, f32)> {
+    let angle = value.trim().parse::<f32>().ok()?;
+    Some(if angle == -1.0 {
+        (-90.0, 0.0)
+    } else if angle == -2.0 {
+        (90.0, 0.0)
+    } else {
+        (0.0, angle)
+    })
+}
+
+fn parse_euler(value: &str) -> Option<[f32; 3]> {
+    let angles = parse_position(value)?;
+    Some(angles.to_array())
 }
 
+/// Rotation of an entity with Quake angles in degrees, like `AngleVectors`:
+/// positive pitch looks down, yaw turns counterclockwise from +X and positive roll tilts right.
+/// Bevy's forward (-Z) ends up facing the map direction, converted with `conversion`.
+///
+/// Yaw turns around the map's up axis, so Y-up maps turn around Y where Quake turns around Z.
+pub fn rotation_from_angles(pitch: f32, yaw: f32, roll: f32, conversion: MapConversion) -> Quat {
+    let (sp, cp) = pitch.to_radians().sin_cos();
+    let (sy, cy) = yaw.to_radians().sin_cos();
+    let (sr, cr) = roll.to_radians().sin_cos();
+    let forward = Vec3::new(cp * cy, cp * sy, -sp);
+    let up = Vec3::new(cr * sp * cy + sr * sy, cr * sp * sy - sr * cy, cr * cp);
+    let to_map = |direction: Vec3| match conversion.z_up {
+        true => direction,
+        false => Vec3::new(direction.x, direction.z, -direction.y),
+    };
+    let back = -conversion.to_world_direction(to_map(forward));
+    let up = conversion.to_world_direction(to_map(up));
+    let right = up.cross(back);
+    Quat::from_mat3(&Mat3::from_cols(right, up, back)).normalize()
+}
+
+/// Geometry of faces with a sky texture. It isn't rendered as a regular brush face.
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct SkySurface;
+
 #[derive(Default, Component, Reflect, Debug)]
 #[reflect(Component)]
 pub struct WorldData {
     pub id: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use shalrath::repr::{Brush, Brushes, Entity, Properties, Property};
+
+    use super::{parse_rotation, MapBrushEntity, MapPointEntity};
+    use crate::qmap::{error::QMapError, settings::MapConversion};
+
+    fn properties(pairs: &[(&str, &str)]) -> Properties {
+        Properties(
+            pairs
+                .iter()
+                .map(|(key, value)| Property {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn invalid_origin() {
+        let props = properties(&[("classname", "light_point"), ("origin", "0 zero 0")]);
+        match MapPointEntity::from_properties(3, &props, MapConversion::default()) {
+            Err(QMapError::InvalidProperty { entity, key, value }) => {
+                assert_eq!(3, entity);
+                assert_eq!("origin", key);
+                assert_eq!("0 zero 0", value);
+            }
+            _ => panic!("Expected invalid origin"),
+        }
+    }
+
+    #[test]
+    fn invalid_angle() {
+        let props = properties(&[("classname", "info_player_start"), ("angle", "north")]);
+        assert!(matches!(
+            MapPointEntity::from_properties(0, &props, MapConversion::default()),
+            Err(QMapError::InvalidProperty { key, .. }) if key == "angle"
+        ));
+    }
+
+    fn rotation(classname: &str, key: &str, value: &str) -> bevy::prelude::Quat {
+        let properties = [(key.to_string(), value.to_string())].into_iter().collect();
+        parse_rotation(classname, &properties, MapConversion::default()).unwrap()
+    }
+
+    fn assert_rotation(expected: bevy::prelude::Quat, actual: bevy::prelude::Quat) {
+        assert!(
+            expected.angle_between(actual) < 0.001,
+            "expected {expected:?}, got {actual:?}"
+        );
+    }
+
+    #[test]
+    fn orientation() {
+        use bevy::prelude::{Quat, Vec3};
+        use std::f32::consts::FRAC_PI_2;
+
+        // Map +X is Bevy +X, map +Y is Bevy -Z
+        let east = Quat::from_rotation_y(-FRAC_PI_2);
+        assert_rotation(east, rotation("info_null", "angle", "0"));
+        assert_rotation(Quat::IDENTITY, rotation("info_null", "angle", "90"));
+        assert_rotation(
+            Quat::from_rotation_y(FRAC_PI_2),
+            rotation("info_null", "angle", "180"),
+        );
+        assert_rotation(
+            east,
+            parse_rotation("info_null", &Default::default(), MapConversion::default()).unwrap(),
+        );
+
+        // Up and down keep the yaw 0 orientation, pitched
+        let up = rotation("info_null", "angle", "-1");
+        assert_rotation(east * Quat::from_rotation_x(FRAC_PI_2), up);
+        assert!((up * Vec3::NEG_Z).abs_diff_eq(Vec3::Y, 0.001));
+        let down = rotation("info_null", "angle", "-2");
+        assert!((down * Vec3::NEG_Z).abs_diff_eq(Vec3::NEG_Y, 0.001));
+
+        assert_rotation(
+            east * Quat::from_rotation_x(-30f32.to_radians()),
+            rotation("info_null", "angles", "30 0 0"),
+        );
+        assert_rotation(
+            Quat::from_rotation_z(-45f32.to_radians()),
+            rotation("info_null", "angles", "0 90 45"),
+        );
+        assert_rotation(
+            Quat::from_rotation_x(-FRAC_PI_2),
+            rotation("light_point", "mangle", "90 -90 0"),
+        );
+        assert_rotation(
+            Quat::from_rotation_x(-FRAC_PI_2),
+            rotation("info_intermission", "mangle", "90 90 0"),
+        );
+    }
+
+    #[test]
+    fn invalid_angles() {
+        let props = properties(&[
+            ("classname", "info_player_start"),
+            ("angle", "90"),
+            ("angles", "0 90"),
+        ]);
+        assert!(matches!(
+            MapPointEntity::from_properties(0, &props, MapConversion::default()),
+            Err(QMapError::InvalidProperty { key, .. }) if key == "angles"
+        ));
+    }
+
+    #[test]
+    fn brush_entity() {
+        let door = Entity {
+            properties: properties(&[("classname", "func_door"), ("speed", "100")]),
+            brushes: Brushes(vec![Brush::default()]),
+        };
+        let entity = MapBrushEntity::from_entity(&door).unwrap();
+        assert_eq!("func_door", entity.name);
+        assert_eq!(Some(&"100".to_string()), entity.properties.get("speed"));
+
+        let point = Entity {
+            brushes: Brushes::default(),
+            ..door
+        };
+        assert!(MapBrushEntity::from_entity(&point).is_none());
+
+        let worldspawn = Entity {
+            properties: properties(&[("classname", "worldspawn")]),
+            brushes: Brushes(vec![Brush::default()]),
+        };
+        assert!(MapBrushEntity::from_entity(&worldspawn).is_none());
+    }
+
+    #[test]
+    fn properties_survive_scene_spawn() {
+        use bevy::{ecs::reflect::ReflectComponent, prelude::*};
+
+        let mut registry = bevy::reflect::TypeRegistryInternal::default();
+        registry.register::<MapPointEntity>();
+        let registration = registry
+            .get(std::any::TypeId::of::<MapPointEntity>())
+            .unwrap();
+        let reflect_component = registration.data::<ReflectComponent>().unwrap();
+
+        // The scene spawner copies components through reflection
+        let mut scene_world = World::new();
+        let scene_entity = scene_world
+            .spawn()
+            .insert(
+                MapPointEntity::from_properties(
+                    0,
+                    &properties(&[("classname", "light_point"), ("range", "4")]),
+                    MapConversion {
+                        scale: 0.5,
+                        z_up: false,
+                    },
+                )
+                .unwrap()
+                .unwrap(),
+            )
+            .id();
+        let mut world = World::new();
+        let entity = world.spawn().id();
+        reflect_component.copy(&scene_world, &mut world, scene_entity, entity);
+
+        let copy = world.get::<MapPointEntity>(entity).unwrap();
+        assert_eq!("light_point", copy.name);
+        assert_eq!(Some(&"4".to_string()), copy.properties.get("range"));
+        assert_eq!(0.5, copy.conversion.scale);
+    }
+
+    #[test]
+    fn converted_origin() {
+        use bevy::prelude::Vec3;
+
+        let props = properties(&[("classname", "info_null"), ("origin", "32 64 16")]);
+        let quake = MapPointEntity::from_properties(0, &props, MapConversion::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(Vec3::new(2.0, 1.0, -4.0), quake.transform.translation);
+
+        let conversion = MapConversion {
+            scale: 0.5,
+            z_up: false,
+        };
+        let y_up = MapPointEntity::from_properties(0, &props, conversion)
+            .unwrap()
+            .unwrap();
+        assert_eq!(Vec3::new(16.0, 32.0, 8.0), y_up.transform.translation);
+        assert_eq!(quake.transform.rotation, y_up.transform.rotation);
+    }
+
+    #[test]
+    fn y_up_orientation() {
+        use bevy::prelude::Vec3;
+
+        let conversion = MapConversion {
+            scale: 1.0,
+            z_up: false,
+        };
+        let forward = |key: &str, value: &str| {
+            let props = properties(&[("classname", "info_null"), (key, value)]);
+            let entity = MapPointEntity::from_properties(0, &props, conversion)
+                .unwrap()
+                .unwrap();
+            entity.transform.rotation * Vec3::NEG_Z
+        };
+
+        // Y-up maps are already in Bevy's axes, yaw turns around Y and pitch towards it
+        assert!(forward("angle", "0").abs_diff_eq(Vec3::X, 0.001));
+        assert!(forward("angle", "90").abs_diff_eq(Vec3::NEG_Z, 0.001));
+        assert!(forward("angle", "-1").abs_diff_eq(Vec3::Y, 0.001));
+        let (sin, cos) = 30f32.to_radians().sin_cos();
+        assert!(forward("angles", "30 180 0").abs_diff_eq(Vec3::new(-cos, -sin, 0.0), 0.001));
+        assert!(forward("angles", "-30 90 0").abs_diff_eq(Vec3::new(0.0, sin, -cos), 0.001));
+    }
+}
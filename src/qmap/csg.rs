@@ -0,0 +1,306 @@
+use bevy::prelude::*;
+
+use super::{
+    build::{MapSurfaces, ToolTexture},
+    types::*,
+};
+
+const EPSILON: f32 = 0.01;
+
+/// Remove the parts of brush faces that are inside or pressed against another brush.
+/// Works in map coordinates, returns the visible faces for each brush.
+/// Faces may be split into multiple convex pieces.
+pub fn cull_hidden_faces(brushes: &[Vec<Face>], surfaces: &MapSurfaces) -> Vec<Vec<Face>> {
+    let bounds: Vec<Option<(Vec3, Vec3)>> =
+        brushes.iter().map(|faces| brush_bounds(faces)).collect();
+    // Clip and trigger brushes are invisible and glass or grates can be seen through,
+    // so they can't hide anything
+    let occluders: Vec<bool> = brushes
+        .iter()
+        .map(|faces| {
+            faces
+                .iter()
+                .all(|face| match ToolTexture::from_texture(&face.texture) {
+                    Some(tool) => tool.occludes(),
+                    None => surfaces.is_opaque(&face.texture),
+                })
+        })
+        .collect();
+
+    brushes
+        .iter()
+        .enumerate()
+        .map(|(index, faces)| {
+            let mut visible: Vec<Face> = faces
+                .iter()
+                .filter(|face| face.vertices.len() >= 3)
+                .cloned()
+                .collect();
+            for (other_index, other) in brushes.iter().enumerate() {
+                if other_index == index
+                    || !occluders[other_index]
+                    || !bounds_overlap(bounds[index], bounds[other_index])
+                {
+                    continue;
+                }
+                let planes: Vec<Plane> = other.iter().map(|face| face.plane).collect();
+                // Of two coincident faces, the one from the earlier brush is kept
+                let keep_coplanar = index < other_index;
+                visible = visible
+                    .iter()
+                    .flat_map(|face| clip_face(face, &planes, keep_coplanar))
+                    .collect();
+            }
+            visible
+        })
+        .collect()
+}
+
+/// Returns the pieces of the face that are outside of the convex brush
+fn clip_face(face: &Face, brush: &[Plane], keep_coplanar: bool) -> Vec<Face> {
+    // Skip faces that don't touch the brush at all to avoid splitting them needlessly
+    if brush.iter().any(|plane| in_front(face, plane)) {
+        return vec![face.clone()];
+    }
+
+    let mut outside: Vec<Face> = vec![];
+    let mut remaining = face.clone();
+    for plane in brush.iter() {
+        let distances: Vec<f32> = remaining
+            .vertices
+            .iter()
+            .map(|vertex| plane.normal.dot(vertex.position) - plane.distance)
+            .collect();
+
+        if distances.iter().all(|distance| distance.abs() <= EPSILON) {
+            // Faces facing the same way on the same plane are duplicates
+            if face.plane.normal.dot(plane.normal) > 0.0 && keep_coplanar {
+                return vec![face.clone()];
+            }
+            // Faces facing each other are hidden where they overlap
+            continue;
+        }
+
+        if distances.iter().all(|distance| *distance >= -EPSILON) {
+            // Completely in front of one of the planes, so nothing is inside the brush
+            outside.push(remaining);
+            return outside;
+        }
+
+        let (front, back) = split_face(&remaining, &distances);
+        if let Some(front) = front {
+            outside.push(front);
+        }
+        match back {
+            Some(back) => remaining = back,
+            None => return outside,
+        }
+    }
+    // Whatever is left is inside the brush
+    outside
+}
+
+fn in_front(face: &Face, plane: &Plane) -> bool {
+    let distances: Vec<f32> = face
+        .vertices
+        .iter()
+        .map(|vertex| plane.normal.dot(vertex.position) - plane.distance)
+        .collect();
+    distances.iter().all(|distance| *distance >= -EPSILON)
+        && distances.iter().any(|distance| *distance > EPSILON)
+}
+
+/// Split a convex face by the plane the distances were measured from
+fn split_face(face: &Face, distances: &[f32]) -> (Option<Face>, Option<Face>) {
+    let mut front: Vec<Vertex> = vec![];
+    let mut back: Vec<Vertex> = vec![];
+    let count = face.vertices.len();
+    for i in 0..count {
+        let j = (i + 1) % count;
+        let (a, b) = (face.vertices[i], face.vertices[j]);
+        let (da, db) = (distances[i], distances[j]);
+
+        if da >= -EPSILON {
+            front.push(a);
+        }
+        if da <= EPSILON {
+            back.push(a);
+        }
+        if (da > EPSILON && db < -EPSILON) || (da < -EPSILON && db > EPSILON) {
+            let t = da / (da - db);
+            let vertex = Vertex {
+                position: a.position.lerp(b.position, t),
+                normal: a.normal,
+                uv: a.uv.lerp(b.uv, t),
+            };
+            front.push(vertex);
+            back.push(vertex);
+        }
+    }
+
+    let to_face = |vertices: Vec<Vertex>| {
+        if vertices.len() >= 3 {
+            Some(Face {
+                plane: face.plane,
+                texture: face.texture.clone(),
+                vertices,
+            })
+        } else {
+            None
+        }
+    };
+    (to_face(front), to_face(back))
+}
+
+fn brush_bounds(faces: &[Face]) -> Option<(Vec3, Vec3)> {
+    faces
+        .iter()
+        .flat_map(|face| face.vertices.iter())
+        .fold(None, |bounds, vertex| match bounds {
+            Some((min, max)) => Some((
+                Vec3::min(min, vertex.position),
+                Vec3::max(max, vertex.position),
+            )),
+            None => Some((vertex.position, vertex.position)),
+        })
+}
+
+fn bounds_overlap(a: Option<(Vec3, Vec3)>, b: Option<(Vec3, Vec3)>) -> bool {
+    match (a, b) {
+        (Some((a_min, a_max)), Some((b_min, b_max))) => {
+            (a_min - EPSILON).cmple(b_max).all() && (b_min - EPSILON).cmple(a_max).all()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bevy::prelude::*;
+    use shalrath::repr::Map;
+
+    use super::cull_hidden_faces;
+    use crate::qmap::{
+        build::MapSurfaces,
+        loader::faces_from_brush,
+        material::{MaterialAlphaMode, MaterialDefinition, TextureMaterial},
+        types::Face,
+    };
+
+    fn brushes(source: &str) -> Vec<Vec<Face>> {
+        let map = source.parse::<Map>().unwrap();
+        map.0[0]
+            .brushes
+            .iter()
+            .map(|brush| faces_from_brush(brush, &HashMap::new()))
+            .collect()
+    }
+
+    fn area(face: &Face) -> f32 {
+        let first = face.vertices[0].position;
+        face.vertices
+            .windows(2)
+            .skip(1)
+            .map(|pair| {
+                (pair[0].position - first)
+                    .cross(pair[1].position - first)
+                    .length()
+                    / 2.0
+            })
+            .sum()
+    }
+
+    fn area_facing(faces: &[Face], normal: Vec3) -> f32 {
+        faces
+            .iter()
+            .filter(|face| face.plane.normal.abs_diff_eq(normal, 0.001))
+            .map(area)
+            .sum()
+    }
+
+    #[test]
+    fn single_brush_is_untouched() {
+        let brushes = brushes(include_str!("../../assets/levels/cube.map"));
+        let visible = cull_hidden_faces(&brushes, &MapSurfaces::default());
+        assert_eq!(brushes[0].len(), visible[0].len());
+    }
+
+    #[test]
+    fn separate_brushes_are_untouched() {
+        let brushes = brushes(include_str!("../../assets/levels/in_hull.map"));
+        let visible = cull_hidden_faces(&brushes, &MapSurfaces::default());
+        for (faces, visible) in brushes.iter().zip(visible.iter()) {
+            assert_eq!(faces.len(), visible.len());
+        }
+    }
+
+    #[test]
+    fn touching_faces_are_removed() {
+        let brushes = brushes(include_str!("../../assets/levels/tests/csg_touching.map"));
+        let visible = cull_hidden_faces(&brushes, &MapSurfaces::default());
+        assert_eq!(5, visible[0].len());
+        assert_eq!(5, visible[1].len());
+        assert_eq!(0.0, area_facing(&visible[0], Vec3::Z));
+        assert_eq!(0.0, area_facing(&visible[1], Vec3::NEG_Z));
+    }
+
+    #[test]
+    fn brush_inside_brush_is_removed() {
+        let brushes = brushes(include_str!("../../assets/levels/tests/csg_inside.map"));
+        let visible = cull_hidden_faces(&brushes, &MapSurfaces::default());
+        assert_eq!(6, visible[0].len());
+        assert!(visible[1].is_empty());
+    }
+
+    #[test]
+    fn partially_covered_face_is_clipped() {
+        let brushes = brushes(include_str!("../../assets/levels/tests/csg_partial.map"));
+        let visible = cull_hidden_faces(&brushes, &MapSurfaces::default());
+        let top = area_facing(&visible[0], Vec3::Z);
+        assert!((top - (128.0 * 128.0 - 32.0 * 32.0)).abs() < 0.1, "{top}");
+        assert_eq!(128.0 * 128.0, area_facing(&visible[0], Vec3::NEG_Z));
+        assert_eq!(0.0, area_facing(&visible[1], Vec3::NEG_Z));
+        assert_eq!(5, visible[1].len());
+    }
+
+    #[test]
+    fn tool_brushes_dont_occlude() {
+        let brushes = brushes(include_str!("../../assets/levels/tests/tool_textures.map"));
+        let visible = cull_hidden_faces(&brushes, &MapSurfaces::default());
+        assert_eq!(6, visible[0].len());
+        assert_eq!(128.0 * 128.0, area_facing(&visible[0], Vec3::Z));
+    }
+
+    #[test]
+    fn duplicate_brush_keeps_one_copy() {
+        let brushes = brushes(include_str!("../../assets/levels/tests/csg_duplicate.map"));
+        let visible = cull_hidden_faces(&brushes, &MapSurfaces::default());
+        assert_eq!(6, visible[0].len());
+        assert!(visible[1].is_empty());
+    }
+
+    #[test]
+    fn see_through_brushes_dont_occlude() {
+        // A window standing on a wall
+        let brushes = brushes(include_str!("../../assets/levels/tests/csg_glass.map"));
+        for alpha_mode in [MaterialAlphaMode::Blend, MaterialAlphaMode::Mask(0.5)] {
+            let material = TextureMaterial {
+                definition: MaterialDefinition {
+                    alpha_mode,
+                    ..default()
+                },
+                ..default()
+            };
+            let surfaces =
+                MapSurfaces::new(HashMap::from([("station/glass_1".to_string(), material)]));
+            let visible = cull_hidden_faces(&brushes, &surfaces);
+            assert_eq!(6, visible[0].len());
+            assert_eq!(64.0 * 64.0, area_facing(&visible[0], Vec3::Z));
+            // The wall still hides the bottom of the window
+            assert_eq!(5, visible[1].len());
+            assert_eq!(0.0, area_facing(&visible[1], Vec3::NEG_Z));
+        }
+    }
+}
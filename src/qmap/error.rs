@@ -0,0 +1,260 @@
+use std::{fmt::Display, string::FromUtf8Error};
+
+use bevy::{math::UVec2, render::mesh::GenerateTangentsError};
+use shalrath::repr::BrushPlane;
+
+#[derive(Debug)]
+pub enum QMapError {
+    InvalidUtf8(FromUtf8Error),
+    /// The map could not be parsed, `line` is where the parser gave up
+    Syntax {
+        line: usize,
+        text: String,
+    },
+    InvalidProperty {
+        entity: usize,
+        key: String,
+        value: String,
+    },
+    InvalidBrush {
+        entity: usize,
+        brush: usize,
+        error: BrushError,
+    },
+}
+
+#[derive(Debug)]
+pub enum BrushError {
+    NoVertices,
+    Tangents(GenerateTangentsError),
+}
+
+/// A typed entity property that couldn't be read, see `FromMapProperties`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyError {
+    Missing {
+        key: String,
+    },
+    Invalid {
+        key: String,
+        value: String,
+        /// Description of the expected value, e.g. "a color"
+        expected: &'static str,
+    },
+    /// The key isn't defined for the entity's class in the FGD
+    Unknown {
+        key: String,
+    },
+}
+
+/// The skybox named by the worldspawn `sky` key could not be built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkyError {
+    /// There are neither six faces nor an equirectangular image with the name
+    NotFound {
+        name: String,
+    },
+    /// Some of the six faces exist, but not this one
+    MissingFace {
+        path: String,
+    },
+    Unreadable {
+        path: String,
+        message: String,
+    },
+    /// Faces must be square and all the same size
+    FaceSize {
+        path: String,
+        size: UVec2,
+        expected: UVec2,
+    },
+    /// Equirectangular images must be twice as wide as they are high, and at least 2 pixels high
+    EquirectangularSize {
+        path: String,
+        size: UVec2,
+    },
+}
+
+/// A WAD file or one of its textures could not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WadError {
+    /// Not a WAD2 or WAD3 file, or the directory is cut off
+    InvalidHeader,
+    /// The lump lies outside the file or its texture is cut off
+    InvalidLump { name: String },
+    /// No editor writes compressed lumps, so they aren't supported
+    Compressed { name: String },
+    /// WAD2 textures need the Quake palette
+    MissingPalette,
+}
+
+/// The FGD file could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FgdError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl QMapError {
+    /// Create a syntax error from the input the parser failed to consume.
+    /// The parser backtracks to the start of the failing block, so the first line
+    /// that doesn't parse on its own is reported when one can be found.
+    pub fn syntax(source: &str, remaining: &str) -> Self {
+        if let Some((index, text)) = source
+            .lines()
+            .enumerate()
+            .find(|(_, line)| !is_valid_line(line))
+        {
+            return QMapError::Syntax {
+                line: index + 1,
+                text: text.trim().to_string(),
+            };
+        }
+        let consumed = source.len().saturating_sub(remaining.len());
+        let line = source[..consumed].matches('\n').count() + 1;
+        let text = remaining
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        QMapError::Syntax { line, text }
+    }
+}
+
+fn is_valid_line(line: &str) -> bool {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with("//") || line == "{" || line == "}" {
+        return true;
+    }
+    if line.starts_with('"') {
+        return line.ends_with('"') && line.matches('"').count() == 4;
+    }
+    line.parse::<BrushPlane>().is_ok()
+}
+
+impl Display for QMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QMapError::InvalidUtf8(err) => write!(f, "map is not valid utf-8: {err}"),
+            QMapError::Syntax { line, text } => {
+                write!(f, "failed to parse map at line {line}: \"{text}\"")
+            }
+            QMapError::InvalidProperty { entity, key, value } => {
+                write!(
+                    f,
+                    "entity {entity}: invalid value \"{value}\" for \"{key}\""
+                )
+            }
+            QMapError::InvalidBrush {
+                entity,
+                brush,
+                error,
+            } => write!(f, "entity {entity}, brush {brush}: {error}"),
+        }
+    }
+}
+
+impl Display for BrushError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BrushError::NoVertices => write!(f, "brush has no vertices"),
+            BrushError::Tangents(err) => write!(f, "failed to generate tangents: {err}"),
+        }
+    }
+}
+
+impl Display for PropertyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PropertyError::Missing { key } => write!(f, "missing \"{key}\""),
+            PropertyError::Invalid {
+                key,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value \"{value}\" for \"{key}\", expected {expected}"
+            ),
+            PropertyError::Unknown { key } => write!(f, "unknown key \"{key}\""),
+        }
+    }
+}
+
+impl Display for SkyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkyError::NotFound { name } => write!(
+                f,
+                "no sky \"{name}\", expected six {name}_<rt|lf|bk|ft|up|dn>.png faces or an equirectangular {name}.png"
+            ),
+            SkyError::MissingFace { path } => write!(f, "missing sky face {path}"),
+            SkyError::Unreadable { path, message } => {
+                write!(f, "could not read {path}: {message}")
+            }
+            SkyError::FaceSize {
+                path,
+                size,
+                expected,
+            } => write!(
+                f,
+                "{path} is {}x{}, expected {}x{}",
+                size.x, size.y, expected.x, expected.y
+            ),
+            SkyError::EquirectangularSize { path, size } => write!(
+                f,
+                "{path} is {}x{}, equirectangular skies must be twice as wide as high and at least 4x2",
+                size.x, size.y
+            ),
+        }
+    }
+}
+
+impl Display for WadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WadError::InvalidHeader => write!(f, "not a WAD2 or WAD3 file"),
+            WadError::InvalidLump { name } => write!(f, "lump \"{name}\" is truncated"),
+            WadError::Compressed { name } => write!(f, "lump \"{name}\" is compressed"),
+            WadError::MissingPalette => write!(
+                f,
+                "the WAD2 has no palette lump and there's no {}",
+                super::wad::QUAKE_PALETTE_PATH
+            ),
+        }
+    }
+}
+
+impl Display for FgdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to parse FGD at line {}: {}",
+            self.line, self.message
+        )
+    }
+}
+
+impl std::error::Error for QMapError {}
+
+impl std::error::Error for PropertyError {}
+
+impl std::error::Error for FgdError {}
+
+impl std::error::Error for SkyError {}
+
+impl std::error::Error for WadError {}
+
+impl std::error::Error for BrushError {}
+
+impl From<FromUtf8Error> for QMapError {
+    fn from(err: FromUtf8Error) -> Self {
+        QMapError::InvalidUtf8(err)
+    }
+}
+
+impl From<GenerateTangentsError> for BrushError {
+    fn from(err: GenerateTangentsError) -> Self {
+        BrushError::Tangents(err)
+    }
+}
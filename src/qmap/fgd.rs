@@ -0,0 +1,856 @@
+use std::fmt;
+
+use bevy::{asset::LoadContext, prelude::*, utils::HashMap};
+
+use super::{
+    error::{FgdError, PropertyError},
+    logic::is_output_key,
+    properties::FromMapValue,
+    settings::COLLIDER_MODE_KEY,
+};
+
+/// Keys the loader understands for every entity, or editors write into worldspawn like
+/// `mapversion` and `_tb_*`. They don't need to be in the FGD.
+const BUILTIN_KEYS: [&str; 9] = [
+    "classname",
+    "origin",
+    "angle",
+    "angles",
+    "mangle",
+    "target",
+    "targetname",
+    "killtarget",
+    "mapversion",
+];
+
+/// Entity definitions from a TrenchBroom FGD file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fgd {
+    pub classes: Vec<FgdClass>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FgdClassKind {
+    Solid,
+    Point,
+    /// Only used through `base(...)` of other classes
+    Base,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FgdClass {
+    pub kind: FgdClassKind,
+    pub name: String,
+    pub description: String,
+    pub base: Vec<String>,
+    /// Bounding box in map units
+    pub size: Option<(Vec3, Vec3)>,
+    pub color: Option<Color>,
+    pub properties: Vec<FgdProperty>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FgdProperty {
+    pub name: String,
+    pub kind: FgdPropertyKind,
+    pub display_name: String,
+    pub default: Option<String>,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FgdPropertyKind {
+    String,
+    Integer,
+    Float,
+    Color255,
+    Color1,
+    Choices(Vec<FgdChoice>),
+    Flags(Vec<FgdFlag>),
+    TargetSource,
+    TargetDestination,
+    /// Types like `studio` or `sound` that are only used by the editor
+    Other(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FgdChoice {
+    pub value: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FgdFlag {
+    pub value: u32,
+    pub name: String,
+    pub default: bool,
+}
+
+impl Fgd {
+    pub fn parse(source: &str) -> Result<Self, FgdError> {
+        Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        }
+        .parse()
+    }
+
+    pub fn class(&self, name: &str) -> Option<&FgdClass> {
+        self.classes.iter().find(|class| class.name == name)
+    }
+
+    /// Properties of the class including the ones from its base classes.
+    /// A property defined again in a derived class replaces the inherited one.
+    pub fn properties(&self, name: &str) -> Vec<&FgdProperty> {
+        let mut properties: Vec<&FgdProperty> = vec![];
+        self.collect_properties(name, &mut properties, 0);
+        properties
+    }
+
+    fn collect_properties<'a>(
+        &'a self,
+        name: &str,
+        properties: &mut Vec<&'a FgdProperty>,
+        depth: usize,
+    ) {
+        // Guards against base classes that include each other
+        const MAX_DEPTH: usize = 16;
+        let class = match self.class(name) {
+            Some(class) if depth < MAX_DEPTH => class,
+            _ => return,
+        };
+        for base in class.base.iter() {
+            self.collect_properties(base, properties, depth + 1);
+        }
+        for property in class.properties.iter() {
+            properties.retain(|existing| existing.name != property.name);
+            properties.push(property);
+        }
+    }
+
+    /// Fill in missing properties that have a default and check the rest against their types.
+    /// Returns `None` if the class isn't defined.
+    pub fn apply(
+        &self,
+        classname: &str,
+        properties: &mut HashMap<String, String>,
+    ) -> Option<Vec<PropertyError>> {
+        self.class(classname)?;
+        let definitions = self.properties(classname);
+        let mut errors: Vec<PropertyError> = vec![];
+
+        let mut keys: Vec<&String> = properties.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = &properties[key];
+            match definitions.iter().find(|property| &property.name == key) {
+                Some(definition) => {
+                    if let Err(expected) = definition.kind.validate(value) {
+                        errors.push(PropertyError::Invalid {
+                            key: key.clone(),
+                            value: value.clone(),
+                            expected,
+                        });
+                    }
+                }
+                None if !is_builtin_key(key) => {
+                    errors.push(PropertyError::Unknown { key: key.clone() })
+                }
+                None => {}
+            }
+        }
+
+        for definition in definitions {
+            if let Some(default) = definition.default_value() {
+                properties.entry(definition.name.clone()).or_insert(default);
+            }
+        }
+        Some(errors)
+    }
+}
+
+fn is_builtin_key(key: &str) -> bool {
+    BUILTIN_KEYS.contains(&key)
+        || key == COLLIDER_MODE_KEY
+        || key.starts_with("_tb_")
+        || is_output_key(key)
+}
+
+impl FgdProperty {
+    /// The default as it would be written in the map, flags default to the sum of their set bits
+    pub fn default_value(&self) -> Option<String> {
+        match &self.kind {
+            FgdPropertyKind::Flags(flags) => Some(
+                flags
+                    .iter()
+                    .filter(|flag| flag.default)
+                    .fold(0, |sum, flag| sum | flag.value)
+                    .to_string(),
+            ),
+            _ => self.default.clone(),
+        }
+    }
+}
+
+impl FgdPropertyKind {
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "string" => FgdPropertyKind::String,
+            "integer" => FgdPropertyKind::Integer,
+            "float" => FgdPropertyKind::Float,
+            "color255" => FgdPropertyKind::Color255,
+            "color1" => FgdPropertyKind::Color1,
+            "choices" => FgdPropertyKind::Choices(vec![]),
+            "flags" => FgdPropertyKind::Flags(vec![]),
+            "target_source" => FgdPropertyKind::TargetSource,
+            "target_destination" => FgdPropertyKind::TargetDestination,
+            other => FgdPropertyKind::Other(other.to_string()),
+        }
+    }
+
+    /// Checks a map value, the error describes the expected value
+    pub fn validate(&self, value: &str) -> Result<(), &'static str> {
+        let valid = match self {
+            FgdPropertyKind::Integer => i64::from_map_value(value).is_some(),
+            FgdPropertyKind::Float => f32::from_map_value(value).is_some(),
+            FgdPropertyKind::Color255 | FgdPropertyKind::Color1 => {
+                Color::from_map_value(value).is_some()
+            }
+            FgdPropertyKind::Choices(choices) => {
+                choices.is_empty() || choices.iter().any(|choice| choice.value == value.trim())
+            }
+            FgdPropertyKind::Flags(_) => u32::from_map_value(value).is_some(),
+            _ => true,
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(self.expected()),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            FgdPropertyKind::String => "string",
+            FgdPropertyKind::Integer => "integer",
+            FgdPropertyKind::Float => "float",
+            FgdPropertyKind::Color255 => "color255",
+            FgdPropertyKind::Color1 => "color1",
+            FgdPropertyKind::Choices(_) => "choices",
+            FgdPropertyKind::Flags(_) => "flags",
+            FgdPropertyKind::TargetSource => "target_source",
+            FgdPropertyKind::TargetDestination => "target_destination",
+            FgdPropertyKind::Other(name) => name,
+        }
+    }
+
+    fn expected(&self) -> &'static str {
+        match self {
+            FgdPropertyKind::Integer | FgdPropertyKind::Flags(_) => "an integer",
+            FgdPropertyKind::Float => "a number",
+            FgdPropertyKind::Color255 | FgdPropertyKind::Color1 => "a color",
+            FgdPropertyKind::Choices(_) => "one of the choices",
+            _ => "a string",
+        }
+    }
+}
+
+/// Writes the definitions in the format read by `Fgd::parse`
+impl fmt::Display for Fgd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, class) in self.classes.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{class}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FgdClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let directive = match self.kind {
+            FgdClassKind::Solid => "@SolidClass",
+            FgdClassKind::Point => "@PointClass",
+            FgdClassKind::Base => "@BaseClass",
+        };
+        write!(f, "{directive}")?;
+        if !self.base.is_empty() {
+            write!(f, " base({})", self.base.join(", "))?;
+        }
+        if let Some((min, max)) = self.size {
+            write!(
+                f,
+                " size({} {} {}, {} {} {})",
+                min.x, min.y, min.z, max.x, max.y, max.z
+            )?;
+        }
+        if let Some(color) = self.color {
+            let [r, g, b, _] = color.as_rgba_f32().map(|c| (c * 255.0).round() as u8);
+            write!(f, " color({r} {g} {b})")?;
+        }
+        write!(f, " = {}", self.name)?;
+        if !self.description.is_empty() {
+            write!(f, " : {}", quoted(&self.description))?;
+        }
+        if self.properties.is_empty() {
+            return writeln!(f, " []");
+        }
+        writeln!(f, "\n[")?;
+        for property in self.properties.iter() {
+            write!(f, "{property}")?;
+        }
+        writeln!(f, "]")
+    }
+}
+
+impl fmt::Display for FgdProperty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    {}({})", self.name, self.kind.name())?;
+        let default = match &self.kind {
+            // The flags list has the defaults
+            FgdPropertyKind::Flags(_) => None,
+            _ => self.default.as_deref(),
+        };
+        // Trailing parts are left out when they're empty
+        let parts = [
+            (!self.display_name.is_empty()).then(|| quoted(&self.display_name)),
+            default.map(|default| match is_word(default) {
+                true => default.to_string(),
+                false => quoted(default),
+            }),
+            (!self.description.is_empty()).then(|| quoted(&self.description)),
+        ];
+        let count = parts
+            .iter()
+            .rposition(|part| part.is_some())
+            .map_or(0, |last| last + 1);
+        for part in parts[..count].iter() {
+            match part {
+                Some(part) => write!(f, " : {part}")?,
+                None => write!(f, " :")?,
+            }
+        }
+        match &self.kind {
+            FgdPropertyKind::Choices(choices) if !choices.is_empty() => {
+                writeln!(f, " =\n    [")?;
+                for choice in choices.iter() {
+                    let value = match is_word(&choice.value) {
+                        true => choice.value.clone(),
+                        false => quoted(&choice.value),
+                    };
+                    writeln!(f, "        {value} : {}", quoted(&choice.name))?;
+                }
+                writeln!(f, "    ]")
+            }
+            FgdPropertyKind::Flags(flags) if !flags.is_empty() => {
+                writeln!(f, " =\n    [")?;
+                for flag in flags.iter() {
+                    writeln!(
+                        f,
+                        "        {} : {} : {}",
+                        flag.value,
+                        quoted(&flag.name),
+                        flag.default as u8
+                    )?;
+                }
+                writeln!(f, "    ]")
+            }
+            _ => writeln!(f),
+        }
+    }
+}
+
+/// FGD strings can't escape quotes, so they're replaced
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "'"))
+}
+
+/// Values that can be written without quotes
+fn is_word(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(is_word_char)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/')
+}
+
+/// Read and parse the FGD, problems are logged and `None` returned so the map can still load
+pub async fn load_fgd<'a>(path: &str, load_context: &LoadContext<'a>) -> Option<Fgd> {
+    let bytes = match load_context.read_asset_bytes(path).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            warn!("Could not read {path}, entity properties are not checked: {err}");
+            return None;
+        }
+    };
+    match Fgd::parse(&String::from_utf8_lossy(&bytes)) {
+        Ok(fgd) => Some(fgd),
+        Err(err) => {
+            warn!("Could not load {path}, entity properties are not checked: {err}");
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// `@SolidClass`, `@include`, ...
+    Directive(String),
+    /// Names, numbers and other unquoted words
+    Word(String),
+    String(String),
+    Symbol(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, FgdError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let start = line;
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            text.push(c);
+                        }
+                        None => {
+                            return Err(FgdError {
+                                line: start,
+                                message: "unterminated string".to_string(),
+                            })
+                        }
+                    }
+                }
+                tokens.push((Token::String(text), start));
+            }
+            '@' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                    name.push(c);
+                }
+                tokens.push((Token::Directive(name.to_lowercase()), line));
+            }
+            c if is_word_char(c) => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                    word.push(c);
+                }
+                tokens.push((Token::Word(word), line));
+            }
+            c => tokens.push((Token::Symbol(c), line)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn parse(mut self) -> Result<Fgd, FgdError> {
+        let mut fgd = Fgd::default();
+        while let Some(token) = self.next() {
+            let kind = match token {
+                Token::Directive(name) => match name.as_str() {
+                    "solidclass" => FgdClassKind::Solid,
+                    "pointclass" => FgdClassKind::Point,
+                    "baseclass" => FgdClassKind::Base,
+                    "include" => {
+                        let file = self.string()?;
+                        warn!("FGD includes are not supported, skipping {file}");
+                        continue;
+                    }
+                    _ => return Err(self.error(&format!("unknown directive @{name}"))),
+                },
+                _ => return Err(self.error("expected a class")),
+            };
+            fgd.classes.push(self.class(kind)?);
+        }
+        Ok(fgd)
+    }
+
+    fn class(&mut self, kind: FgdClassKind) -> Result<FgdClass, FgdError> {
+        let mut class = FgdClass {
+            kind,
+            name: String::new(),
+            description: String::new(),
+            base: vec![],
+            size: None,
+            color: None,
+            properties: vec![],
+        };
+        // Attributes like `base(...)` and `size(...)` before the name
+        while !self.accept(&Token::Symbol('=')) {
+            let attribute = self.word()?.to_lowercase();
+            self.expect('(')?;
+            let args = self.arguments()?;
+            match attribute.as_str() {
+                "base" => {
+                    class.base = args
+                        .split(|arg| *arg == ",")
+                        .flat_map(|names| names.iter().map(|name| name.to_string()))
+                        .collect()
+                }
+                "size" => class.size = Some(self.size(&args)?),
+                "color" => {
+                    class.color = Some(
+                        Color::from_map_value(&args.join(" "))
+                            .ok_or_else(|| self.error("invalid color"))?,
+                    )
+                }
+                _ => {}
+            }
+        }
+        class.name = self.word()?;
+        if self.accept(&Token::Symbol(':')) {
+            class.description = self.string()?;
+        }
+        self.expect('[')?;
+        while !self.accept(&Token::Symbol(']')) {
+            class.properties.push(self.property()?);
+        }
+        Ok(class)
+    }
+
+    fn property(&mut self) -> Result<FgdProperty, FgdError> {
+        let name = self.word()?;
+        self.expect('(')?;
+        let kind = FgdPropertyKind::from_name(&self.word()?);
+        self.expect(')')?;
+        self.accept(&Token::Word("readonly".to_string()));
+
+        // `: "Display name" : default : "Description"`, every part is optional
+        let mut parts: Vec<Option<String>> = vec![];
+        while parts.len() < 3 && self.accept(&Token::Symbol(':')) {
+            parts.push(match self.peek() {
+                Some(Token::String(_)) => Some(self.string()?),
+                Some(Token::Word(_)) if parts.len() == 1 => Some(self.word()?),
+                Some(Token::Word(_)) => return Err(self.error("expected a string")),
+                _ => None,
+            });
+        }
+        let mut parts = parts.into_iter();
+        let mut property = FgdProperty {
+            name,
+            kind,
+            display_name: parts.next().flatten().unwrap_or_default(),
+            default: parts.next().flatten(),
+            description: parts.next().flatten().unwrap_or_default(),
+        };
+
+        match &mut property.kind {
+            FgdPropertyKind::Choices(choices) => {
+                self.expect('=')?;
+                self.expect('[')?;
+                while !self.accept(&Token::Symbol(']')) {
+                    let value = match self.peek() {
+                        Some(Token::String(_)) => self.string()?,
+                        _ => self.word()?,
+                    };
+                    self.expect(':')?;
+                    let name = self.string()?;
+                    choices.push(FgdChoice { value, name });
+                }
+            }
+            FgdPropertyKind::Flags(flags) => {
+                self.expect('=')?;
+                self.expect('[')?;
+                while !self.accept(&Token::Symbol(']')) {
+                    let value = self
+                        .word()?
+                        .parse::<u32>()
+                        .map_err(|_| self.error("flag values must be integers"))?;
+                    self.expect(':')?;
+                    let name = self.string()?;
+                    let default = match self.accept(&Token::Symbol(':')) {
+                        true => self.word()? != "0",
+                        false => false,
+                    };
+                    flags.push(FgdFlag {
+                        value,
+                        name,
+                        default,
+                    });
+                }
+            }
+            _ => {}
+        }
+        Ok(property)
+    }
+
+    /// Words up to the closing parenthesis, commas are kept as separate words
+    fn arguments(&mut self) -> Result<Vec<String>, FgdError> {
+        let mut args = vec![];
+        let mut depth = 0;
+        loop {
+            match self.next() {
+                Some(Token::Symbol(')')) if depth == 0 => return Ok(args),
+                Some(Token::Symbol(c)) => {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    args.push(c.to_string());
+                }
+                Some(Token::Word(word) | Token::String(word) | Token::Directive(word)) => {
+                    args.push(word)
+                }
+                None => return Err(self.error("expected )")),
+            }
+        }
+    }
+
+    /// `size(x y z, x y z)` or `size(x y z)` centered on the origin
+    fn size(&self, args: &[String]) -> Result<(Vec3, Vec3), FgdError> {
+        let numbers = args
+            .iter()
+            .filter(|arg| *arg != ",")
+            .map(|arg| arg.parse::<f32>().ok())
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(|| self.error("invalid size"))?;
+        match numbers[..] {
+            [x, y, z] => {
+                let half = Vec3::new(x, y, z) / 2.0;
+                Ok((-half, half))
+            }
+            [x1, y1, z1, x2, y2, z2] => Ok((Vec3::new(x1, y1, z1), Vec3::new(x2, y2, z2))),
+            _ => Err(self.error("invalid size")),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .map(|(token, _)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), FgdError> {
+        match self.accept(&Token::Symbol(symbol)) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected {symbol}"))),
+        }
+    }
+
+    fn word(&mut self) -> Result<String, FgdError> {
+        match self.peek() {
+            Some(Token::Word(word)) => {
+                let word = word.clone();
+                self.position += 1;
+                Ok(word)
+            }
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    /// A quoted string, `"a" + "b"` is joined into one
+    fn string(&mut self) -> Result<String, FgdError> {
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                Some(Token::String(part)) => {
+                    text.push_str(part);
+                    self.position += 1;
+                }
+                _ => return Err(self.error("expected a string")),
+            }
+            if !self.accept(&Token::Symbol('+')) {
+                return Ok(text);
+            }
+        }
+    }
+
+    fn error(&self, message: &str) -> FgdError {
+        let line = self
+            .tokens
+            .get(self.position.min(self.tokens.len().saturating_sub(1)))
+            .map(|(_, line)| *line)
+            .unwrap_or(1);
+        FgdError {
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{Fgd, FgdClassKind, FgdPropertyKind};
+    use crate::qmap::{error::PropertyError, test_util::properties};
+
+    const DOOR_FGD: &str = r#"
+        // Comments are skipped
+        @BaseClass = Targetname [ targetname(target_source) : "Name" ]
+        @BaseClass base(Targetname) size(32 32 32) = Door
+        [
+            speed(float) : "Speed" : "100" : "Units per second"
+            wait(integer) readonly : "Wait" : 3
+        ]
+        @SolidClass base(Door) color(255 128 0) = func_door : "Door" +
+            " that opens"
+        [
+            wait(integer) : "Wait" : 5
+            sounds(choices) : "Sounds" : 1 =
+            [
+                0 : "Silent"
+                1 : "Stone"
+            ]
+            spawnflags(flags) =
+            [
+                1 : "Starts open" : 0
+                4 : "Don't link" : 1
+            ]
+            message(string) : "Message" : : "Shown when touched"
+        ]
+    "#;
+
+    #[test]
+    fn entity_fgd() {
+        let fgd = Fgd::parse(include_str!("../../assets/entity.fgd")).unwrap();
+        let light = fgd.class("light_point").unwrap();
+        assert_eq!(FgdClassKind::Point, light.kind);
+        assert_eq!("Point light", light.description);
+        assert_eq!(Some((Vec3::splat(-8.0), Vec3::splat(8.0))), light.size);
+        let intensity = &light.properties[0];
+        assert_eq!("intensity", intensity.name);
+        assert_eq!(FgdPropertyKind::Float, intensity.kind);
+        assert_eq!(Some("800".to_string()), intensity.default);
+
+        let player = fgd.class("info_player_start").unwrap();
+        assert_eq!(Some(Color::rgb(0.0, 1.0, 0.0)), player.color);
+        assert_eq!(FgdClassKind::Solid, fgd.class("worldspawn").unwrap().kind);
+    }
+
+    #[test]
+    fn classes_and_properties() {
+        let fgd = Fgd::parse(DOOR_FGD).unwrap();
+        let door = fgd.class("func_door").unwrap();
+        assert_eq!("Door that opens", door.description);
+        assert_eq!(Some(Color::rgb(1.0, 128.0 / 255.0, 0.0)), door.color);
+        assert_eq!(
+            Some((Vec3::splat(-16.0), Vec3::splat(16.0))),
+            fgd.class("Door").unwrap().size
+        );
+
+        let properties = fgd.properties("func_door");
+        let names: Vec<&str> = properties
+            .iter()
+            .map(|property| property.name.as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "targetname",
+                "speed",
+                "wait",
+                "sounds",
+                "spawnflags",
+                "message"
+            ],
+            names
+        );
+        assert_eq!(Some("5".to_string()), properties[2].default);
+        assert_eq!("Units per second", properties[1].description);
+        assert_eq!(None, properties[5].default);
+        assert_eq!("Shown when touched", properties[5].description);
+        match &properties[3].kind {
+            FgdPropertyKind::Choices(choices) => assert_eq!("Stone", choices[1].name),
+            kind => panic!("expected choices, got {kind:?}"),
+        }
+        assert_eq!(Some("4".to_string()), properties[4].default_value());
+    }
+
+    #[test]
+    fn defaults_and_validation() {
+        let fgd = Fgd::parse(DOOR_FGD).unwrap();
+        let mut properties = properties(&[
+            ("classname", "func_door"),
+            ("origin", "0 0 0"),
+            ("speed", "fast"),
+            ("sounds", "3"),
+            ("lip", "8"),
+            ("_tb_layer", "1"),
+            ("mapversion", "220"),
+        ]);
+
+        let errors = fgd.apply("func_door", &mut properties).unwrap();
+        assert_eq!(
+            vec![
+                PropertyError::Unknown {
+                    key: "lip".to_string()
+                },
+                PropertyError::Invalid {
+                    key: "sounds".to_string(),
+                    value: "3".to_string(),
+                    expected: "one of the choices",
+                },
+                PropertyError::Invalid {
+                    key: "speed".to_string(),
+                    value: "fast".to_string(),
+                    expected: "a number",
+                },
+            ],
+            errors
+        );
+        assert_eq!("fast", properties["speed"]);
+        assert_eq!("5", properties["wait"]);
+        assert_eq!("4", properties["spawnflags"]);
+        assert!(!properties.contains_key("message"));
+
+        assert_eq!(None, fgd.apply("func_wall", &mut properties));
+    }
+
+    #[test]
+    fn write_and_parse() {
+        let fgd = Fgd::parse(DOOR_FGD).unwrap();
+        let written = fgd.to_string();
+        assert!(written.contains("@SolidClass base(Door) color(255 128 0) = func_door"));
+        assert!(written.contains("    message(string) : \"Message\" : : \"Shown when touched\"\n"));
+        assert_eq!(fgd, Fgd::parse(&written).unwrap());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let err = Fgd::parse("@PointClass = light\n[\n  range(float) : 15\n]").unwrap_err();
+        assert_eq!(3, err.line);
+        let err = Fgd::parse("@PointClass = light : \"Light\n").unwrap_err();
+        assert_eq!(1, err.line);
+        assert!(Fgd::parse("@NotAClass = light []").is_err());
+    }
+}
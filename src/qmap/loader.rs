@@ -1,16 +1,47 @@
-use std::f32::consts::PI;
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    path::{Path, PathBuf},
+};
 
 use bevy::{
-    asset::{AssetLoader, LoadContext, LoadedAsset},
+    asset::{AssetIoError, AssetLoader, LoadContext, LoadedAsset},
     prelude::*,
+    reflect::TypeUuid,
     utils::BoxedFuture,
 };
-use shalrath::repr::{Brush, Map, TextureOffset};
+use shalrath::repr::{Brush, Entity as QMapEntity, Map, Property, TextureOffset, TexturePlane};
+
+use super::{
+    build::*,
+    component::*,
+    csg,
+    error::{PropertyError, QMapError},
+    fgd::{load_fgd, Fgd},
+    material::{load_texture_materials, texture_map_report},
+    settings::{ColliderMode, MapConversion, MeshMode, QMapSettings, COLLIDER_MODE_KEY},
+    sky::{load_sky, MapSky},
+    texture::load_texture_images,
+    types::*,
+    wad::MapWads,
+    worldspawn::MapWorldspawn,
+    BrushLocation,
+};
 
-use super::{build::*, component::*, convert_coords, types::*};
+pub struct QMapLoader {
+    settings: QMapSettings,
+}
 
-#[derive(Default)]
-pub struct QMapLoader;
+impl FromWorld for QMapLoader {
+    fn from_world(world: &mut World) -> Self {
+        QMapLoader {
+            settings: world
+                .get_resource::<QMapSettings>()
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
 
 impl AssetLoader for QMapLoader {
     fn load<'a>(
@@ -18,7 +49,7 @@ impl AssetLoader for QMapLoader {
         bytes: &'a [u8],
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
-        Box::pin(async move { Ok(load_qmap(bytes, load_context).await?) })
+        Box::pin(async move { load_qmap(bytes, load_context, &self.settings).await })
     }
 
     fn extensions(&self) -> &[&str] {
@@ -26,51 +57,402 @@ impl AssetLoader for QMapLoader {
     }
 }
 
+/// Extension of the worldspawn overrides next to a map, see `apply_sidecar`
+pub const SIDECAR_EXTENSION: &str = "map.ron";
+
+/// Worldspawn overrides of a map, loaded as assets so maps can depend on them
+#[derive(Debug, TypeUuid)]
+#[uuid = "3e3e407e-22c8-4069-815a-15ecf27dc0a6"]
+pub struct MapSidecar(pub HashMap<String, String>);
+
+impl MapSidecar {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ron::Error> {
+        ron::de::from_bytes(bytes).map(MapSidecar)
+    }
+}
+
+#[derive(Default)]
+pub struct MapSidecarLoader;
+
+impl AssetLoader for MapSidecarLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(MapSidecar::from_bytes(bytes)?));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[SIDECAR_EXTENSION]
+    }
+}
+
 async fn load_qmap<'a, 'b>(
     bytes: &'a [u8],
     load_context: &'a mut LoadContext<'b>,
+    settings: &QMapSettings,
 ) -> Result<(), bevy::asset::Error> {
-    let qmap = String::from_utf8(bytes.to_vec())
-        .expect("Failed to parse map as utf-8")
+    let source = String::from_utf8(bytes.to_vec()).map_err(QMapError::from)?;
+    let mut qmap = source
         .parse::<Map>()
-        .expect("Failed to parse map");
+        .map_err(|err| QMapError::syntax(&source, &err.input))?;
+    let sidecar = apply_sidecar(&mut qmap, load_context).await;
+    let worldspawn = worldspawn_properties(&qmap);
+    let conversion = map_conversion(&worldspawn, settings, load_context);
+
+    let sky = match worldspawn.get("sky").map(|name| name.trim()) {
+        Some(name) if !name.is_empty() => match load_sky(name, load_context).await {
+            Ok(cubemap) => Some(MapSky {
+                cubemap: load_context.set_labeled_asset("sky", LoadedAsset::new(cubemap)),
+            }),
+            Err(err) => {
+                warn!("No sky in {:?}: {err}", load_context.path());
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let wads = MapWads::load(&worldspawn, load_context).await;
+    let textures = load_texture_images(&qmap, &wads, load_context).await;
+    let texture_sizes = textures.sizes;
+    let materials = load_texture_materials(
+        &qmap,
+        &wads,
+        textures.images,
+        &settings.glass_textures,
+        load_context,
+    )
+    .await;
+    let report = texture_map_report(&materials);
+    if !report.is_empty() {
+        info!("Texture maps of {:?}:\n{report}", load_context.path());
+    }
+    let mut surfaces = MapSurfaces::new(materials);
+    let fgd = match &settings.fgd_path {
+        Some(path) => load_fgd(path, load_context).await,
+        None => None,
+    };
 
     let mut world = World::default();
     let mut root = world.spawn();
     root.insert_bundle(SpatialBundle::default())
-        .insert(Name::new("map"));
+        .insert(Name::new("map"))
+        .insert(conversion)
+        .insert(MapWorldspawn {
+            properties: worldspawn,
+        });
+    if let Some(sky) = sky {
+        root.insert(sky);
+    }
 
     root.with_children(|builder| {
-        let mut mesh_counter = 0;
-        for entity in qmap.0.iter() {
-            // Entities
-            if let Some(point_entity) = MapPointEntity::from_properties(&entity.properties) {
-                let mut point_entity = point_entity.clone();
-                point_entity.transform = point_entity
-                    .transform
-                    .with_translation(convert_coords(point_entity.transform.translation));
-                build_point_entity(builder, point_entity);
+        for (entity_index, entity) in qmap.0.iter().enumerate() {
+            // Brush entities
+            if let Some(brush_entity) = MapBrushEntity::from_entity(entity) {
+                // Brush entities can move, so their faces are never culled
+                let brushes = entity_faces(entity, &texture_sizes, &surfaces, false, &conversion);
+                let collider_mode = entity_collider_mode(entity, settings, load_context);
+                build_brush_entity(builder, brush_entity, |builder| {
+                    build_entity_brushes(
+                        builder,
+                        load_context,
+                        &mut surfaces,
+                        settings.mesh_mode,
+                        collider_mode,
+                        entity_index,
+                        brushes,
+                    );
+                });
+                continue;
             }
 
-            // Brushes
-            for brush in entity.brushes.iter() {
-                let faces = faces_from_brush(brush)
-                    .iter()
-                    .map(convert_face_coords)
-                    .collect();
-                build_brush(builder, load_context, &mut mesh_counter, faces);
+            // Point entities
+            match MapPointEntity::from_properties(entity_index, &entity.properties, conversion) {
+                Ok(Some(mut point_entity)) => {
+                    if let Some(fgd) = &fgd {
+                        apply_fgd(fgd, entity_index, &mut point_entity, load_context);
+                    }
+                    build_point_entity(builder, point_entity);
+                }
+                Ok(None) => {}
+                // A worldspawn with an invalid key still gets its brushes
+                Err(err) => warn!("Skipping entity in {:?}: {err}", load_context.path()),
             }
+
+            // Worldspawn brushes
+            let brushes = entity_faces(
+                entity,
+                &texture_sizes,
+                &surfaces,
+                settings.cull_hidden_faces,
+                &conversion,
+            );
+            let collider_mode = entity_collider_mode(entity, settings, load_context);
+            build_entity_brushes(
+                builder,
+                load_context,
+                &mut surfaces,
+                settings.mesh_mode,
+                collider_mode,
+                entity_index,
+                brushes,
+            );
         }
     });
 
     let scene = Scene::new(world);
 
-    load_context.set_default_asset(LoadedAsset::new(scene));
+    // Editing the sidecar reloads the map
+    let mut scene = LoadedAsset::new(scene);
+    if let Some(sidecar) = &sidecar {
+        scene = scene.with_dependency(sidecar.as_path().into());
+    }
+    load_context.set_default_asset(scene);
 
     Ok(())
 }
 
-fn faces_from_brush(brush: &Brush) -> Vec<Face> {
+/// Worldspawn keys from `<map>.ron` next to the map, like `{"_scale": "0.03125"}`.
+/// They replace the keys in the map, so imported maps can be adjusted without editing them.
+/// Returns the path of the sidecar if there is one.
+async fn apply_sidecar<'a>(qmap: &mut Map, load_context: &LoadContext<'a>) -> Option<PathBuf> {
+    let path = sidecar_path(load_context.path());
+    let bytes = match load_context.read_asset_bytes(&path).await {
+        Ok(bytes) => bytes,
+        Err(AssetIoError::NotFound(_)) => return None,
+        Err(err) => {
+            warn!("Could not read {path:?}: {err}");
+            return Some(path);
+        }
+    };
+    let overrides = match MapSidecar::from_bytes(&bytes) {
+        Ok(MapSidecar(overrides)) => overrides,
+        Err(err) => {
+            warn!("Could not parse {path:?}, expected a map of worldspawn keys: {err}");
+            return Some(path);
+        }
+    };
+    let worldspawn = match qmap.0.iter_mut().find(|entity| {
+        entity
+            .properties
+            .iter()
+            .any(|property| property.key == "classname" && property.value == "worldspawn")
+    }) {
+        Some(worldspawn) => worldspawn,
+        None => {
+            warn!(
+                "{:?} has no worldspawn, ignoring {path:?}",
+                load_context.path()
+            );
+            return Some(path);
+        }
+    };
+    for (key, value) in overrides {
+        match worldspawn
+            .properties
+            .0
+            .iter_mut()
+            .find(|property| property.key == key)
+        {
+            Some(property) => property.value = value,
+            None => worldspawn.properties.0.push(Property { key, value }),
+        }
+    }
+    Some(path)
+}
+
+/// `levels/station.map` has the sidecar `levels/station.map.ron`
+fn sidecar_path(map_path: &Path) -> PathBuf {
+    let mut path = map_path.as_os_str().to_owned();
+    path.push(".ron");
+    PathBuf::from(path)
+}
+
+/// Keys of the worldspawn entity, empty if the map has none
+fn worldspawn_properties(qmap: &Map) -> bevy::utils::HashMap<String, String> {
+    qmap.0
+        .iter()
+        .map(|entity| properties_to_map(&entity.properties))
+        .find(|properties| properties.get("classname").map(String::as_str) == Some("worldspawn"))
+        .unwrap_or_default()
+}
+
+/// The loader setting with the worldspawn overrides
+fn map_conversion(
+    worldspawn: &bevy::utils::HashMap<String, String>,
+    settings: &QMapSettings,
+    load_context: &LoadContext,
+) -> MapConversion {
+    let (conversion, invalid) = settings.conversion.with_properties(worldspawn);
+    for (key, value) in invalid {
+        warn!(
+            "Invalid {key} value {value:?} in {:?}, using {:?}",
+            load_context.path(),
+            conversion
+        );
+    }
+    conversion
+}
+
+/// Fill in the FGD defaults and flag the keys that don't match the definition
+fn apply_fgd(
+    fgd: &Fgd,
+    entity_index: usize,
+    point_entity: &mut MapPointEntity,
+    load_context: &LoadContext,
+) {
+    let errors = match fgd.apply(&point_entity.name, &mut point_entity.properties) {
+        Some(errors) => errors,
+        None => {
+            warn!(
+                "Entity {entity_index} in {:?}: class {} is not defined in the FGD",
+                load_context.path(),
+                point_entity.name
+            );
+            return;
+        }
+    };
+    for err in errors {
+        warn!("Entity {entity_index} in {:?}: {err}", load_context.path());
+        let key = match err {
+            PropertyError::Invalid { key, .. }
+            | PropertyError::Unknown { key }
+            | PropertyError::Missing { key } => key,
+        };
+        point_entity.invalid_properties.push(key);
+    }
+}
+
+/// The entity's `_collider` key, or the loader setting if it's missing or invalid
+fn entity_collider_mode(
+    entity: &QMapEntity,
+    settings: &QMapSettings,
+    load_context: &LoadContext,
+) -> ColliderMode {
+    let value = match entity
+        .properties
+        .iter()
+        .find(|property| property.key == COLLIDER_MODE_KEY)
+    {
+        Some(property) => &property.value,
+        None => return settings.collider_mode,
+    };
+    ColliderMode::from_property(value).unwrap_or_else(|| {
+        warn!(
+            "Unknown {COLLIDER_MODE_KEY} value {value:?} in {:?}, using {:?}",
+            load_context.path(),
+            settings.collider_mode
+        );
+        settings.collider_mode
+    })
+}
+
+/// All faces and the visible faces of each brush in the entity, in world coordinates.
+/// Only brushes with opaque `surfaces` hide the faces they touch.
+fn entity_faces(
+    entity: &QMapEntity,
+    texture_sizes: &HashMap<String, Vec2>,
+    surfaces: &MapSurfaces,
+    cull_hidden_faces: bool,
+    conversion: &MapConversion,
+) -> Vec<(Vec<Face>, Vec<Face>)> {
+    let faces: Vec<Vec<Face>> = entity
+        .brushes
+        .iter()
+        .map(|brush| faces_from_brush(brush, texture_sizes))
+        .collect();
+    let visible_faces = match cull_hidden_faces {
+        true => csg::cull_hidden_faces(&faces, surfaces),
+        false => faces.clone(),
+    };
+    let convert = |faces: Vec<Face>| {
+        faces
+            .iter()
+            .map(|face| convert_face_coords(face, conversion))
+            .collect()
+    };
+    faces
+        .into_iter()
+        .zip(visible_faces)
+        .map(|(faces, visible_faces)| (convert(faces), convert(visible_faces)))
+        .collect()
+}
+
+fn build_entity_brushes(
+    builder: &mut WorldChildBuilder,
+    load_context: &mut LoadContext,
+    surfaces: &mut MapSurfaces,
+    mesh_mode: MeshMode,
+    collider_mode: ColliderMode,
+    entity_index: usize,
+    brushes: Vec<(Vec<Face>, Vec<Face>)>,
+) {
+    build_entity_collider(builder, collider_mode, entity_index, &brushes);
+    let meshes = entity_meshes(surfaces, mesh_mode, entity_index, &brushes);
+    for (brush_index, ((faces, _), brush_meshes)) in
+        brushes.into_iter().zip(meshes.brushes).enumerate()
+    {
+        match brush_meshes {
+            Ok(brush_meshes) => build_brush(
+                builder,
+                load_context,
+                surfaces,
+                collider_mode,
+                BrushLocation {
+                    entity: entity_index,
+                    brush: brush_index,
+                },
+                faces,
+                brush_meshes,
+            ),
+            Err(err) => warn!("Skipping brush in {:?}: {err}", load_context.path()),
+        }
+    }
+    if let Some(batch) = meshes.batch {
+        batch.build(builder, load_context, surfaces);
+    }
+}
+
+/// Meshes of the brushes of an entity, before anything is spawned
+struct EntityMeshes {
+    /// Meshes of each brush, or why the brush is skipped
+    brushes: Vec<Result<BrushMeshes, QMapError>>,
+    /// Faces merged across brushes in `MeshMode::Merged`
+    batch: Option<MeshBatch>,
+}
+
+fn entity_meshes(
+    surfaces: &MapSurfaces,
+    mesh_mode: MeshMode,
+    entity_index: usize,
+    brushes: &[(Vec<Face>, Vec<Face>)],
+) -> EntityMeshes {
+    let mut batch = match mesh_mode {
+        MeshMode::Merged => Some(MeshBatch::default()),
+        MeshMode::PerFace => None,
+    };
+    let brushes = brushes
+        .iter()
+        .enumerate()
+        .map(|(brush_index, (faces, visible_faces))| {
+            brush_meshes(surfaces, faces, visible_faces, batch.as_mut()).map_err(|error| {
+                QMapError::InvalidBrush {
+                    entity: entity_index,
+                    brush: brush_index,
+                    error,
+                }
+            })
+        })
+        .collect();
+    EntityMeshes { brushes, batch }
+}
+
+pub fn faces_from_brush(brush: &Brush, texture_sizes: &HashMap<String, Vec2>) -> Vec<Face> {
     let mut faces: Vec<Face> = vec![];
     let planes: Vec<_> = brush
         .0
@@ -81,6 +463,10 @@ fn faces_from_brush(brush: &Brush) -> Vec<Face> {
     for p1 in brush.0.iter() {
         let mut vertices: Vec<Vertex> = vec![];
         let plane = Plane::from(p1.plane);
+        let texture_size = texture_sizes
+            .get(&p1.texture)
+            .copied()
+            .unwrap_or(DEFAULT_TEXTURE_SIZE);
         for p2 in brush.0.iter() {
             for p3 in brush.0.iter() {
                 if let Some(position) =
@@ -94,15 +480,13 @@ fn faces_from_brush(brush: &Brush) -> Vec<Face> {
                         let uv = get_vertex_uv(
                             position,
                             plane,
-                            match p1.texture_offset {
-                                TextureOffset::Standard { u, v } => Vec2 { x: u, y: v },
-                                TextureOffset::Valve { u: _u, v: _v } => Vec2::ZERO,
-                            },
+                            p1.texture_offset,
                             p1.angle.to_radians(),
                             Vec2 {
                                 x: p1.scale_x,
                                 y: p1.scale_y,
                             },
+                            texture_size,
                         );
                         vertices.push(Vertex {
                             position,
@@ -123,19 +507,19 @@ fn faces_from_brush(brush: &Brush) -> Vec<Face> {
     faces
 }
 
-fn convert_face_coords(face: &Face) -> Face {
+pub fn convert_face_coords(face: &Face, conversion: &MapConversion) -> Face {
     Face {
         plane: Plane {
-            distance: face.plane.distance,
-            normal: convert_coords(face.plane.normal).normalize(),
+            distance: conversion.to_world_length(face.plane.distance),
+            normal: conversion.to_world_direction(face.plane.normal).normalize(),
         },
         texture: face.texture.clone(),
         vertices: face
             .vertices
             .iter()
             .map(|vert| Vertex {
-                position: convert_coords(vert.position),
-                normal: convert_coords(vert.normal).normalize(),
+                position: conversion.to_world(vert.position),
+                normal: conversion.to_world_direction(vert.normal).normalize(),
                 uv: vert.uv,
             })
             .collect(),
@@ -184,17 +568,55 @@ fn plane_intersection(p1: Plane, p2: Plane, p3: Plane) -> Option<Vec3> {
     )
 }
 
-fn vertex_in_hull(point: Vec3, faces: &Vec<Plane>) -> bool {
+fn vertex_in_hull(point: Vec3, faces: &[Plane]) -> bool {
     !faces.iter().any(|face| {
         let projection = face.normal.dot(point);
         (projection - face.distance) > 0.01
     })
 }
 
-fn get_vertex_uv(point: Vec3, face: Plane, offset: Vec2, angle: f32, scale: Vec2) -> Vec2 {
+fn get_vertex_uv(
+    point: Vec3,
+    face: Plane,
+    texture_offset: TextureOffset,
+    angle: f32,
+    scale: Vec2,
+    texture_size: Vec2,
+) -> Vec2 {
+    let (mut uv, offset) = match texture_offset {
+        TextureOffset::Standard { u, v } => {
+            (get_standard_uv(point, face, angle), Vec2 { x: u, y: v })
+        }
+        // Valve 220 stores the texture axes explicitly, rotation is already baked into them
+        TextureOffset::Valve { u, v } => (
+            Vec2 {
+                x: point.dot(texture_axis(u)),
+                y: point.dot(texture_axis(v)),
+            },
+            Vec2 { x: u.d, y: v.d },
+        ),
+    };
+
+    uv /= texture_size;
+    uv /= scale;
+    uv += offset / texture_size;
+
+    uv
+}
+
+fn texture_axis(plane: TexturePlane) -> Vec3 {
+    Vec3 {
+        x: plane.x,
+        y: plane.y,
+        z: plane.z,
+    }
+}
+
+/// Project the point onto the axis-aligned plane closest to the face (Quake standard format)
+fn get_standard_uv(point: Vec3, face: Plane, angle: f32) -> Vec2 {
     let abs_normal = face.normal.abs();
 
-    let mut uv = if abs_normal.z >= abs_normal.x && abs_normal.z >= abs_normal.y {
+    let uv = if abs_normal.z >= abs_normal.x && abs_normal.z >= abs_normal.y {
         Vec2 {
             x: point.x,
             y: -point.y,
@@ -211,21 +633,13 @@ fn get_vertex_uv(point: Vec3, face: Plane, offset: Vec2, angle: f32, scale: Vec2
         }
     };
 
-    uv = Vec2 {
+    Vec2 {
         x: uv.x * angle.cos() - uv.y * angle.sin(),
         y: uv.x * angle.sin() + uv.y * angle.cos(),
-    };
-
-    // TODO: calculate actual texture size
-    let texture_size = Vec2 { x: 32.0, y: 32.0 };
-    uv /= texture_size;
-    uv /= scale;
-    uv += offset / texture_size;
-
-    uv
+    }
 }
 
-fn order_vertices_counter_clockwise(normal: Vec3, vertices: &mut Vec<Vertex>) {
+fn order_vertices_counter_clockwise(normal: Vec3, vertices: &mut [Vertex]) {
     let mut min: Option<Vec3> = None;
     let mut max: Option<Vec3> = None;
     for vertex in vertices.iter() {
@@ -280,9 +694,156 @@ fn angle_around_axis_normalized(normal: Vec3, from: Vec3, to: Vec3) -> f32 {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use bevy::prelude::*;
+    use shalrath::repr::Map;
+
+    use crate::qmap::{
+        build::MapSurfaces,
+        error::QMapError,
+        loader::{angle_around_axis, entity_faces, entity_meshes, faces_from_brush, sidecar_path},
+        settings::{MapConversion, MeshMode},
+    };
+
+    /// Count the meshes a level produces in per-face and merged mode
+    fn mesh_counts(source: &str) -> (usize, usize) {
+        let map = source.parse::<Map>().unwrap();
+        let surfaces = MapSurfaces::default();
+        let count = |mesh_mode| -> usize {
+            map.0
+                .iter()
+                .enumerate()
+                .map(|(entity_index, entity)| {
+                    let brushes = entity_faces(
+                        entity,
+                        &HashMap::new(),
+                        &surfaces,
+                        false,
+                        &MapConversion::default(),
+                    );
+                    let meshes = entity_meshes(&surfaces, mesh_mode, entity_index, &brushes);
+                    let brush_meshes: usize = meshes
+                        .brushes
+                        .iter()
+                        .map(|brush| brush.as_ref().unwrap().meshes.len())
+                        .sum();
+                    brush_meshes + meshes.batch.map_or(0, |batch| batch.meshes().len())
+                })
+                .sum()
+        };
+        (count(MeshMode::PerFace), count(MeshMode::Merged))
+    }
 
-    use crate::qmap::loader::angle_around_axis;
+    #[test]
+    fn sidecar_next_to_map() {
+        assert_eq!(
+            std::path::Path::new("levels/station.map.ron"),
+            sidecar_path(std::path::Path::new("levels/station.map"))
+        );
+    }
+
+    #[test]
+    fn merged_mesh_count() {
+        for (source, expected) in [
+            (include_str!("../../assets/levels/cube.map"), (6, 2)),
+            (include_str!("../../assets/levels/in_hull.map"), (18, 5)),
+            (include_str!("../../assets/levels/simple.map"), (116, 8)),
+            (include_str!("../../assets/levels/default.map"), (132, 1)),
+            (include_str!("../../assets/levels/station.map"), (705, 11)),
+        ] {
+            assert_eq!(expected, mesh_counts(source));
+        }
+    }
+
+    fn assert_face_uvs(
+        map: &Map,
+        texture_sizes: &HashMap<String, Vec2>,
+        texture: &str,
+        expected: &[(Vec3, Vec2)],
+    ) {
+        let faces = faces_from_brush(&map.0[0].brushes[0], texture_sizes);
+        let face = faces
+            .iter()
+            .find(|face| face.texture == texture)
+            .expect("Face not found");
+        assert_eq!(expected.len(), face.vertices.len());
+        for (position, uv) in expected.iter() {
+            let vertex = face
+                .vertices
+                .iter()
+                .find(|vertex| vertex.position.abs_diff_eq(*position, 0.01))
+                .expect("Vertex not found");
+            assert!(
+                vertex.uv.abs_diff_eq(*uv, 0.0001),
+                "UV mismatch at {position}: expected {uv}, got {}",
+                vertex.uv
+            );
+        }
+    }
+
+    /// The same cube in both formats. The Valve axes are Quake's base axes for each face,
+    /// rotated like qbsp rotates them, which is how editors convert Standard faces to Valve 220.
+    #[test]
+    fn valve_texture_axes_match_standard_alignment() {
+        let standard = include_str!("../../assets/levels/tests/standard_alignment.map")
+            .parse::<Map>()
+            .unwrap();
+        let valve = include_str!("../../assets/levels/tests/valve_alignment.map")
+            .parse::<Map>()
+            .unwrap();
+        let texture_sizes = HashMap::from([
+            ("station/wall_2".to_string(), Vec2::new(64.0, 16.0)),
+            ("station/floor_1".to_string(), Vec2::new(128.0, 32.0)),
+        ]);
+
+        let standard_faces = faces_from_brush(&standard.0[0].brushes[0], &texture_sizes);
+        let valve_faces = faces_from_brush(&valve.0[0].brushes[0], &texture_sizes);
+        assert_eq!(6, valve_faces.len());
+        for (standard, valve) in standard_faces.iter().zip(valve_faces.iter()) {
+            assert_eq!(standard.vertices.len(), valve.vertices.len());
+            for (expected, vertex) in standard.vertices.iter().zip(valve.vertices.iter()) {
+                assert!(
+                    vertex.uv.abs_diff_eq(expected.uv, 0.0001),
+                    "{} at {}: expected {}, got {}",
+                    valve.texture,
+                    vertex.position,
+                    expected.uv,
+                    vertex.uv
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn syntax_error_line() {
+        let source = "{\n\"classname\" \"worldspawn\"\n{\n( 0 0 0 ) ( 0 1 0 ) oops\n}\n}\n";
+        let err = source.parse::<Map>().unwrap_err();
+        match QMapError::syntax(source, &err.input) {
+            QMapError::Syntax { line, .. } => assert_eq!(4, line),
+            err => panic!("Unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn texture_size_from_image() {
+        let map = include_str!("../../assets/levels/valve.map")
+            .parse::<Map>()
+            .unwrap();
+        let texture_sizes = HashMap::from([("station/wall_2".to_string(), Vec2::new(64.0, 16.0))]);
+
+        assert_face_uvs(
+            &map,
+            &texture_sizes,
+            "station/wall_2",
+            &[
+                (Vec3::new(0.0, 0.0, 0.0), Vec2::new(0.0, 0.0)),
+                (Vec3::new(64.0, 0.0, 0.0), Vec2::new(1.0, 0.0)),
+                (Vec3::new(64.0, 0.0, 64.0), Vec2::new(1.0, -4.0)),
+                (Vec3::new(0.0, 0.0, 64.0), Vec2::new(0.0, -4.0)),
+            ],
+        );
+    }
 
     #[test]
     fn greater_than_180_degrees() {
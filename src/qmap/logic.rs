@@ -0,0 +1,843 @@
+use bevy::{
+    ecs::event::ManualEventReader,
+    prelude::*,
+    reflect::FromReflect,
+    utils::{HashMap, HashSet},
+};
+
+use super::{
+    component::{MapBrushEntity, MapPointEntity},
+    properties::FromMapProperties,
+    registry::MapEntityAppExt,
+    target::MapEntityNames,
+};
+
+/// Inputs with a delay of 0 can trigger each other within a frame, up to this many rounds.
+/// Anything left, like a relay triggering itself, continues next frame.
+const MAX_ROUNDS: usize = 32;
+
+/// Source-style entity I/O. Map entities declare outputs as properties, e.g.
+/// `"OnTrigger" "door1,Open,,0.5"`, which sends the `Open` input to every entity named
+/// `door1` half a second after the entity fires `OnTrigger`.
+///
+/// Inputs are handled per classname, see `MapLogicAppExt::register_map_input`.
+/// `Kill` is understood by every entity, other plugins add inputs like that with
+/// `MapLogicAppExt::register_global_map_input`.
+pub struct MapLogicPlugin;
+
+impl Plugin for MapLogicPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<FireOutput>()
+            .init_resource::<MapInputRegistry>()
+            .init_resource::<PendingInputs>()
+            .register_type::<MapOutputs>()
+            .register_type::<LogicRelay>()
+            .register_type::<LogicTimer>()
+            .register_type::<LogicCounter>()
+            .add_system(output_linker)
+            .add_system(logic_timers)
+            .add_system(run_map_logic.exclusive_system().at_end())
+            .register_map_class(spawn_logic_relay)
+            .register_map_class(spawn_logic_timer)
+            .register_map_class(spawn_logic_counter);
+
+        register_switch_inputs::<LogicRelay>(app, "logic_relay");
+        register_switch_inputs::<LogicTimer>(app, "logic_timer");
+        app.register_map_input("logic_relay", "Trigger", relay_trigger)
+            .register_map_input("logic_timer", "FireTimer", |world, input| {
+                world.send_event(FireOutput::new(input.target, "OnTimer", input.activator));
+            })
+            .register_map_input("logic_counter", "Add", |world, input| {
+                counter_input(world, input, |value, amount| value + amount)
+            })
+            .register_map_input("logic_counter", "Subtract", |world, input| {
+                counter_input(world, input, |value, amount| value - amount)
+            })
+            .register_map_input("logic_counter", "SetValue", |world, input| {
+                counter_input(world, input, |_, amount| amount)
+            })
+            .register_global_map_input("Kill", |world, input| {
+                world.entity_mut(input.target).despawn_recursive();
+            });
+    }
+}
+
+/// Sent to make a map entity fire one of its outputs
+#[derive(Clone, Debug)]
+pub struct FireOutput {
+    pub entity: Entity,
+    pub output: String,
+    /// The entity that started the chain, like the player pressing a button
+    pub activator: Option<Entity>,
+    /// Used by connections that don't have their own parameter
+    pub parameter: String,
+}
+
+impl FireOutput {
+    pub fn new(entity: Entity, output: &str, activator: Option<Entity>) -> Self {
+        FireOutput {
+            entity,
+            output: output.to_string(),
+            activator,
+            parameter: String::new(),
+        }
+    }
+}
+
+/// An input received by a map entity
+#[derive(Clone, Debug)]
+pub struct MapInput {
+    pub target: Entity,
+    /// The entity whose output sent the input
+    pub caller: Entity,
+    pub activator: Option<Entity>,
+    pub input: String,
+    pub parameter: String,
+}
+
+/// One `target,input,parameter,delay,times` connection of an output
+#[derive(Clone, Debug, Default, PartialEq, Reflect, FromReflect)]
+pub struct MapConnection {
+    pub output: String,
+    /// `targetname` of the receivers, `!self` and `!activator` are also understood
+    pub target: String,
+    pub input: String,
+    pub parameter: String,
+    /// Seconds
+    pub delay: f32,
+    /// How many more times the connection fires, -1 for always
+    pub times: i32,
+}
+
+impl MapConnection {
+    /// Parse the value of an output key, the fields are separated by commas
+    /// (or ESC like in Source maps). Several connections can be separated by `;`.
+    pub fn parse_all(output: &str, value: &str) -> Option<Vec<Self>> {
+        value
+            .split(';')
+            .filter(|connection| !connection.trim().is_empty())
+            .map(|connection| Self::parse(output, connection))
+            .collect()
+    }
+
+    pub fn parse(output: &str, value: &str) -> Option<Self> {
+        let fields: Vec<&str> = value
+            .split([',', '\u{1b}'])
+            .map(|field| field.trim())
+            .collect();
+        if fields.len() < 2 || fields.len() > 5 || fields[0].is_empty() || fields[1].is_empty() {
+            return None;
+        }
+        let number = |index: usize| fields.get(index).filter(|field| !field.is_empty());
+        Some(MapConnection {
+            output: output.to_string(),
+            target: fields[0].to_string(),
+            input: fields[1].to_string(),
+            parameter: fields.get(2).unwrap_or(&"").to_string(),
+            delay: match number(3) {
+                Some(delay) => delay.parse::<f32>().ok().filter(|delay| *delay >= 0.0)?,
+                None => 0.0,
+            },
+            times: match number(4) {
+                Some(times) => times.parse::<i32>().ok()?,
+                None => -1,
+            },
+        })
+    }
+}
+
+/// Output keys start with `On` or `Out` and a capital letter, like `OnTrigger` or `OutValue`
+pub fn is_output_key(key: &str) -> bool {
+    ["On", "Out"].iter().any(|prefix| {
+        key.strip_prefix(prefix)
+            .and_then(|rest| rest.chars().next())
+            .map_or(false, |c| c.is_ascii_uppercase())
+    })
+}
+
+/// Connections of a map entity's outputs
+#[derive(Default, Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct MapOutputs(pub Vec<MapConnection>);
+
+pub type MapInputHandler = Box<dyn Fn(&mut World, &MapInput) + Send + Sync>;
+
+/// Input handlers by classname and input name
+#[derive(Default)]
+pub struct MapInputRegistry {
+    handlers: HashMap<(String, String), MapInputHandler>,
+    /// Handlers of inputs every map entity understands, by input name
+    global_handlers: HashMap<String, MapInputHandler>,
+    /// Inputs without a handler that have already been reported
+    reported: HashSet<(String, String)>,
+}
+
+pub trait MapLogicAppExt {
+    /// Call `handler` when an entity of `classname` receives `input`
+    fn register_map_input(
+        &mut self,
+        classname: &str,
+        input: &str,
+        handler: impl Fn(&mut World, &MapInput) + Send + Sync + 'static,
+    ) -> &mut Self;
+
+    /// Call `handler` when any map entity receives `input`, unless its classname has its own
+    fn register_global_map_input(
+        &mut self,
+        input: &str,
+        handler: impl Fn(&mut World, &MapInput) + Send + Sync + 'static,
+    ) -> &mut Self;
+}
+
+impl MapLogicAppExt for App {
+    fn register_map_input(
+        &mut self,
+        classname: &str,
+        input: &str,
+        handler: impl Fn(&mut World, &MapInput) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.init_resource::<MapInputRegistry>();
+        let key = (classname.to_string(), input.to_string());
+        if self
+            .world
+            .resource_mut::<MapInputRegistry>()
+            .handlers
+            .insert(key, Box::new(handler))
+            .is_some()
+        {
+            warn!("Replacing the handler of input {input} of map entity {classname}");
+        }
+        self
+    }
+
+    fn register_global_map_input(
+        &mut self,
+        input: &str,
+        handler: impl Fn(&mut World, &MapInput) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.init_resource::<MapInputRegistry>();
+        if self
+            .world
+            .resource_mut::<MapInputRegistry>()
+            .global_handlers
+            .insert(input.to_string(), Box::new(handler))
+            .is_some()
+        {
+            warn!("Replacing the handler of input {input} of every map entity");
+        }
+        self
+    }
+}
+
+/// Inputs waiting for their delay
+#[derive(Default)]
+pub struct PendingInputs {
+    inputs: Vec<PendingInput>,
+    /// Reads `FireOutput` without draining it, so other systems still see the events
+    fired: ManualEventReader<FireOutput>,
+}
+
+struct PendingInput {
+    delay: f32,
+    connection: MapConnection,
+    caller: Entity,
+    activator: Option<Entity>,
+}
+
+/// Reads the outputs of newly spawned map entities
+pub fn output_linker(
+    mut commands: Commands,
+    point_query: Query<(Entity, &MapPointEntity), Added<MapPointEntity>>,
+    brush_query: Query<(Entity, &MapBrushEntity), Added<MapBrushEntity>>,
+) {
+    let added = point_query
+        .iter()
+        .map(|(entity, map_entity)| (entity, &map_entity.name, &map_entity.properties))
+        .chain(
+            brush_query
+                .iter()
+                .map(|(entity, map_entity)| (entity, &map_entity.name, &map_entity.properties)),
+        );
+    for (entity, classname, properties) in added {
+        let mut keys: Vec<&String> = properties.keys().filter(|key| is_output_key(key)).collect();
+        keys.sort();
+        let mut connections = vec![];
+        for key in keys {
+            match MapConnection::parse_all(key, &properties[key]) {
+                Some(parsed) => connections.extend(parsed),
+                None => warn!(
+                    "Map entity {classname} has an invalid output {key} \"{}\", expected target,input,parameter,delay,times",
+                    properties[key]
+                ),
+            }
+        }
+        if !connections.is_empty() {
+            commands.entity(entity).insert(MapOutputs(connections));
+        }
+    }
+}
+
+/// Queues the connections of fired outputs, then sends the inputs that are due
+pub fn run_map_logic(world: &mut World) {
+    let delta = world.resource::<Time>().delta_seconds();
+    for pending in world.resource_mut::<PendingInputs>().inputs.iter_mut() {
+        pending.delay -= delta;
+    }
+
+    for _ in 0..MAX_ROUNDS {
+        queue_fired_outputs(world);
+        let due: Vec<PendingInput> = {
+            let mut pending = world.resource_mut::<PendingInputs>();
+            let (due, waiting) = pending
+                .inputs
+                .drain(..)
+                .partition(|pending| pending.delay <= 0.0);
+            pending.inputs = waiting;
+            due
+        };
+        if due.is_empty() {
+            return;
+        }
+        for pending in due {
+            for input in resolve_input(world, pending) {
+                dispatch_input(world, &input);
+            }
+        }
+    }
+    queue_fired_outputs(world);
+}
+
+fn queue_fired_outputs(world: &mut World) {
+    let fired: Vec<FireOutput> = world.resource_scope(|world, mut pending: Mut<PendingInputs>| {
+        let events = world.resource::<Events<FireOutput>>();
+        pending.fired.iter(events).cloned().collect()
+    });
+    for event in fired {
+        let mut outputs = match world.get_mut::<MapOutputs>(event.entity) {
+            Some(outputs) => outputs,
+            None => continue,
+        };
+        let mut queued = vec![];
+        for connection in outputs.0.iter_mut() {
+            if connection.output != event.output || connection.times == 0 {
+                continue;
+            }
+            if connection.times > 0 {
+                connection.times -= 1;
+            }
+            let mut connection = connection.clone();
+            if connection.parameter.is_empty() {
+                connection.parameter = event.parameter.clone();
+            }
+            queued.push(PendingInput {
+                delay: connection.delay,
+                connection,
+                caller: event.entity,
+                activator: event.activator,
+            });
+        }
+        world.resource_mut::<PendingInputs>().inputs.extend(queued);
+    }
+}
+
+/// One input per entity with the target name, in the map of the caller
+fn resolve_input(world: &World, pending: PendingInput) -> Vec<MapInput> {
+    let connection = pending.connection;
+    let targets = match connection.target.as_str() {
+        "!self" => vec![pending.caller],
+        "!activator" => pending.activator.into_iter().collect(),
+        name => {
+            let map = world
+                .get::<Parent>(pending.caller)
+                .map(|parent| parent.get());
+            world.resource::<MapEntityNames>().get(map, name).to_vec()
+        }
+    };
+    if targets.is_empty() {
+        warn!(
+            "No entity named {} for output {}, input {} is dropped",
+            connection.target, connection.output, connection.input
+        );
+    }
+    targets
+        .into_iter()
+        .map(|target| MapInput {
+            target,
+            caller: pending.caller,
+            activator: pending.activator,
+            input: connection.input.clone(),
+            parameter: connection.parameter.clone(),
+        })
+        .collect()
+}
+
+fn dispatch_input(world: &mut World, input: &MapInput) {
+    let classname = match classname(world, input.target) {
+        Some(classname) => classname,
+        // Killed by an earlier input
+        None => return,
+    };
+    world.resource_scope(|world, mut registry: Mut<MapInputRegistry>| {
+        let key = (classname, input.input.clone());
+        let handler = registry
+            .handlers
+            .get(&key)
+            .or_else(|| registry.global_handlers.get(&input.input));
+        match handler {
+            Some(handler) => handler(world, input),
+            None => {
+                if registry.reported.insert(key.clone()) {
+                    warn!("Map entity {} has no handler for input {}", key.0, key.1);
+                }
+            }
+        }
+    });
+}
+
+fn classname(world: &World, entity: Entity) -> Option<String> {
+    let entity = world.get_entity(entity)?;
+    entity
+        .get::<MapPointEntity>()
+        .map(|map_entity| map_entity.name.clone())
+        .or_else(|| {
+            entity
+                .get::<MapBrushEntity>()
+                .map(|map_entity| map_entity.name.clone())
+        })
+}
+
+/// Logic entities that can be turned on and off with `Enable`, `Disable` and `Toggle`
+trait Switchable: Component {
+    fn enabled_mut(&mut self) -> &mut bool;
+}
+
+fn register_switch_inputs<T: Switchable>(app: &mut App, classname: &str) {
+    type Switch = fn(bool) -> bool;
+    let switches: [(&str, Switch); 3] = [
+        ("Enable", |_| true),
+        ("Disable", |_| false),
+        ("Toggle", |enabled| !enabled),
+    ];
+    for (input, switch) in switches {
+        app.register_map_input(classname, input, move |world, input| {
+            if let Some(mut component) = world.get_mut::<T>(input.target) {
+                let enabled = component.enabled_mut();
+                *enabled = switch(*enabled);
+            }
+        });
+    }
+}
+
+/// Fires `OnTrigger` when it receives `Trigger`
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct LogicRelay {
+    pub enabled: bool,
+}
+
+impl Switchable for LogicRelay {
+    fn enabled_mut(&mut self) -> &mut bool {
+        &mut self.enabled
+    }
+}
+
+#[derive(FromMapProperties)]
+#[map(
+    point_class = "logic_relay",
+    description = "Fires OnTrigger when triggered, unless disabled",
+    size = "-8 -8 -8, 8 8 8",
+    color = "0 128 255"
+)]
+struct LogicRelayProperties {
+    #[map(
+        rename = "StartDisabled",
+        default,
+        display = "Start disabled",
+        description = "Ignore Trigger until enabled"
+    )]
+    start_disabled: bool,
+}
+
+fn spawn_logic_relay(
+    commands: &mut Commands,
+    entity: Entity,
+    _: &MapPointEntity,
+    properties: LogicRelayProperties,
+) {
+    commands.entity(entity).insert(LogicRelay {
+        enabled: !properties.start_disabled,
+    });
+}
+
+fn relay_trigger(world: &mut World, input: &MapInput) {
+    let enabled = world
+        .get::<LogicRelay>(input.target)
+        .map_or(false, |relay| relay.enabled);
+    if enabled {
+        world.send_event(FireOutput::new(input.target, "OnTrigger", input.activator));
+    }
+}
+
+/// Fires `OnTimer` every `RefireTime` seconds while enabled
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct LogicTimer {
+    pub enabled: bool,
+    #[reflect(ignore)]
+    pub timer: Timer,
+}
+
+impl Switchable for LogicTimer {
+    fn enabled_mut(&mut self) -> &mut bool {
+        &mut self.enabled
+    }
+}
+
+#[derive(FromMapProperties)]
+#[map(
+    point_class = "logic_timer",
+    description = "Fires OnTimer at an interval",
+    size = "-8 -8 -8, 8 8 8",
+    color = "0 128 255"
+)]
+struct LogicTimerProperties {
+    #[map(
+        rename = "RefireTime",
+        default = 1.0,
+        display = "Refire time",
+        description = "Seconds between OnTimer outputs"
+    )]
+    refire_time: f32,
+    #[map(
+        rename = "StartDisabled",
+        default,
+        display = "Start disabled",
+        description = "Wait for Enable"
+    )]
+    start_disabled: bool,
+}
+
+fn spawn_logic_timer(
+    commands: &mut Commands,
+    entity: Entity,
+    map_entity: &MapPointEntity,
+    properties: LogicTimerProperties,
+) {
+    if properties.refire_time <= 0.0 {
+        warn!(
+            "Skipping {}: RefireTime must be above 0, got {}",
+            map_entity.name, properties.refire_time
+        );
+        return;
+    }
+    commands.entity(entity).insert(LogicTimer {
+        enabled: !properties.start_disabled,
+        timer: Timer::from_seconds(properties.refire_time, true),
+    });
+}
+
+pub fn logic_timers(
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut LogicTimer)>,
+    mut fire: EventWriter<FireOutput>,
+) {
+    for (entity, mut logic_timer) in query.iter_mut() {
+        if !logic_timer.enabled {
+            continue;
+        }
+        logic_timer.timer.tick(time.delta());
+        for _ in 0..logic_timer.timer.times_finished_this_tick() {
+            fire.send(FireOutput::new(entity, "OnTimer", None));
+        }
+    }
+}
+
+/// Keeps a value between `min` and `max`, changed with `Add`, `Subtract` and `SetValue`.
+/// Fires `OutValue` with the new value, and `OnHitMin`/`OnHitMax` when it reaches a limit.
+/// Like in Source, a `max` of 0 means there's no upper limit.
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct LogicCounter {
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+#[derive(FromMapProperties)]
+#[map(
+    point_class = "logic_counter",
+    description = "Counts up and down, firing outputs at its limits",
+    size = "-8 -8 -8, 8 8 8",
+    color = "0 128 255"
+)]
+struct LogicCounterProperties {
+    #[map(
+        rename = "startvalue",
+        default,
+        display = "Start value",
+        description = "Initial value"
+    )]
+    start_value: f32,
+    #[map(default, description = "OnHitMin is fired when the value reaches it")]
+    min: f32,
+    #[map(
+        default,
+        description = "OnHitMax is fired when the value reaches it, 0 for no upper limit"
+    )]
+    max: f32,
+}
+
+fn spawn_logic_counter(
+    commands: &mut Commands,
+    entity: Entity,
+    _: &MapPointEntity,
+    properties: LogicCounterProperties,
+) {
+    commands.entity(entity).insert(LogicCounter {
+        value: properties.start_value,
+        min: properties.min,
+        max: properties.max,
+    });
+}
+
+fn counter_input(world: &mut World, input: &MapInput, change: impl Fn(f32, f32) -> f32) {
+    let amount = match input.parameter.trim().parse::<f32>() {
+        Ok(amount) => amount,
+        Err(_) => {
+            warn!(
+                "logic_counter input {} needs a number, got \"{}\"",
+                input.input, input.parameter
+            );
+            return;
+        }
+    };
+    let mut counter = match world.get_mut::<LogicCounter>(input.target) {
+        Some(counter) => counter,
+        None => return,
+    };
+    let previous = counter.value;
+    let max = if counter.max == 0.0 {
+        f32::INFINITY
+    } else {
+        counter.max.max(counter.min)
+    };
+    counter.value = change(previous, amount).clamp(counter.min, max);
+    let (value, min) = (counter.value, counter.min);
+    if value == previous {
+        return;
+    }
+    world.send_event(FireOutput {
+        parameter: value.to_string(),
+        ..FireOutput::new(input.target, "OutValue", input.activator)
+    });
+    if value >= max {
+        world.send_event(FireOutput::new(input.target, "OnHitMax", input.activator));
+    } else if value <= min {
+        world.send_event(FireOutput::new(input.target, "OnHitMin", input.activator));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::prelude::*;
+
+    use super::{
+        counter_input, is_output_key, FireOutput, LogicCounter, LogicRelay, MapConnection,
+        MapInput, MapLogicAppExt, MapLogicPlugin,
+    };
+    use crate::qmap::{
+        registry::map_entity_dispatcher,
+        target::{target_linker, MapEntityNames},
+        test_util::{brush, point},
+    };
+
+    /// Inputs received by `func_door`, with the time they arrived
+    #[derive(Default)]
+    struct DoorInputs(Vec<(String, f32)>);
+
+    /// Runs a frame `seconds` after the previous one
+    fn advance(app: &mut App, start: Instant, seconds: f32) {
+        let last = app.world.resource::<Time>().last_update().unwrap_or(start);
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(last + Duration::from_secs_f32(seconds));
+        app.update();
+    }
+
+    #[test]
+    fn connections() {
+        assert_eq!(
+            Some(MapConnection {
+                output: "OnTrigger".to_string(),
+                target: "door1".to_string(),
+                input: "Open".to_string(),
+                parameter: String::new(),
+                delay: 0.5,
+                times: -1,
+            }),
+            MapConnection::parse("OnTrigger", "door1,Open,,0.5")
+        );
+        let connections =
+            MapConnection::parse_all("OnTrigger", "a\u{1b}Add\u{1b}2\u{1b}0\u{1b}1; b,Kill")
+                .unwrap();
+        assert_eq!(2, connections.len());
+        assert_eq!(
+            ("2", 1),
+            (connections[0].parameter.as_str(), connections[0].times)
+        );
+        assert_eq!("b", connections[1].target);
+        assert_eq!(None, MapConnection::parse("OnTrigger", "door1"));
+        assert_eq!(None, MapConnection::parse("OnTrigger", "door1,Open,,soon"));
+        assert!(is_output_key("OnTrigger"));
+        assert!(is_output_key("OutValue"));
+        assert!(!is_output_key("origin"));
+        assert!(!is_output_key("Once"));
+    }
+
+    #[test]
+    fn relay_counter_door() {
+        let mut app = App::new();
+        let start = Instant::now();
+        let mut time = Time::default();
+        time.update_with_instant(start);
+        app.insert_resource(time)
+            .init_resource::<MapEntityNames>()
+            .init_resource::<DoorInputs>()
+            .add_plugin(MapLogicPlugin)
+            .add_system(map_entity_dispatcher)
+            .add_system(target_linker)
+            .register_map_input("func_door", "Open", |world, input| {
+                let now = world.resource::<Time>().seconds_since_startup() as f32;
+                world
+                    .resource_mut::<DoorInputs>()
+                    .0
+                    .push((input.input.clone(), now));
+            });
+
+        let button = brush(&mut app, "func_button", &[("OnPressed", "relay1,Trigger")]);
+        let relay = point(
+            &mut app,
+            "logic_relay",
+            &[
+                ("targetname", "relay1"),
+                ("OnTrigger", "counter1,Add,2,0.5"),
+            ],
+        );
+        let counter = point(
+            &mut app,
+            "logic_counter",
+            &[
+                ("targetname", "counter1"),
+                ("max", "4"),
+                ("OnHitMax", "door1,Open,,0,1;!self,Kill,,1"),
+            ],
+        );
+        let door = brush(&mut app, "func_door", &[("targetname", "door1")]);
+        app.world
+            .spawn()
+            .push_children(&[button, relay, counter, door]);
+        app.update();
+
+        for _ in 0..2 {
+            app.world
+                .send_event(FireOutput::new(button, "OnPressed", Some(button)));
+            advance(&mut app, start, 0.1);
+        }
+        assert!(app.world.resource::<DoorInputs>().0.is_empty());
+
+        // The counter reaches 4 when the second Add arrives, 0.5s after the second press
+        advance(&mut app, start, 0.45);
+        assert_eq!(2.0, app.world.get::<LogicCounter>(counter).unwrap().value);
+        assert!(app.world.resource::<DoorInputs>().0.is_empty());
+        advance(&mut app, start, 0.1);
+        let inputs = &app.world.resource::<DoorInputs>().0;
+        assert_eq!(1, inputs.len());
+        assert_eq!("Open", inputs[0].0);
+        assert!((inputs[0].1 - 0.75).abs() < 0.001);
+
+        // Disabled relays ignore Trigger, and the counter kills itself after a second
+        app.world
+            .send_event(FireOutput::new(button, "OnPressed", None));
+        app.world.get_mut::<LogicRelay>(relay).unwrap().enabled = false;
+        advance(&mut app, start, 1.1);
+        assert!(app.world.get_entity(counter).is_none());
+        assert_eq!(1, app.world.resource::<DoorInputs>().0.len());
+    }
+
+    #[test]
+    fn timer() {
+        let mut app = App::new();
+        let start = Instant::now();
+        let mut time = Time::default();
+        time.update_with_instant(start);
+        app.insert_resource(time)
+            .init_resource::<MapEntityNames>()
+            .add_plugin(MapLogicPlugin)
+            .add_system(map_entity_dispatcher)
+            .add_system(target_linker);
+
+        let timer = point(
+            &mut app,
+            "logic_timer",
+            &[("RefireTime", "0.5"), ("OnTimer", "counter1,Add,1")],
+        );
+        let counter = point(
+            &mut app,
+            "logic_counter",
+            &[("targetname", "counter1"), ("max", "10")],
+        );
+        app.world.spawn().push_children(&[timer, counter]);
+        app.update();
+
+        for _ in 0..5 {
+            advance(&mut app, start, 0.3);
+        }
+        let value = app.world.get::<LogicCounter>(counter).unwrap().value;
+        assert_eq!(3.0, value);
+    }
+
+    #[test]
+    fn fired_outputs_stay_readable() {
+        let mut app = App::new();
+        app.insert_resource(Time::default())
+            .init_resource::<MapEntityNames>()
+            .add_plugin(MapLogicPlugin);
+        let button = brush(&mut app, "func_button", &[("OnPressed", "relay1,Trigger")]);
+        app.world
+            .send_event(FireOutput::new(button, "OnPressed", None));
+        app.update();
+
+        let events = app.world.resource::<Events<FireOutput>>();
+        let mut reader = events.get_reader();
+        let outputs: Vec<_> = reader.iter(events).map(|event| event.entity).collect();
+        assert_eq!(vec![button], outputs);
+    }
+
+    #[test]
+    fn counter_without_max() {
+        let mut world = World::new();
+        world.init_resource::<Events<FireOutput>>();
+        let counter = world.spawn().insert(LogicCounter::default()).id();
+        let add = MapInput {
+            target: counter,
+            caller: counter,
+            activator: None,
+            input: "Add".to_string(),
+            parameter: "5".to_string(),
+        };
+        for _ in 0..2 {
+            counter_input(&mut world, &add, |value, amount| value + amount);
+        }
+
+        assert_eq!(10.0, world.get::<LogicCounter>(counter).unwrap().value);
+        let events = world.resource::<Events<FireOutput>>();
+        let mut reader = events.get_reader();
+        let outputs: Vec<_> = reader
+            .iter(events)
+            .map(|event| (event.output.as_str(), event.parameter.as_str()))
+            .collect();
+        assert_eq!(vec![("OutValue", "5"), ("OutValue", "10")], outputs);
+    }
+}
@@ -0,0 +1,677 @@
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+use bevy::{
+    asset::{AssetIoError, AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{Extent3d, Face, TextureDimension, TextureFormat},
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use shalrath::repr::Map;
+
+use super::{
+    animation::TextureAnimation,
+    texture::{map_textures, read_png, texture_image_path},
+    wad::MapWads,
+};
+
+/// Extension of material definitions, next to the texture's PNG.
+/// Only RON is read, like the rest of the game's assets; TOML definitions are not supported.
+pub const MATERIAL_EXTENSION: &str = "material.ron";
+
+/// `AlphaMode` as written in material definitions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub enum MaterialAlphaMode {
+    #[default]
+    Opaque,
+    /// Cutout, pixels with a lower alpha are discarded
+    Mask(f32),
+    Blend,
+}
+
+impl From<MaterialAlphaMode> for AlphaMode {
+    fn from(alpha_mode: MaterialAlphaMode) -> Self {
+        match alpha_mode {
+            MaterialAlphaMode::Opaque => AlphaMode::Opaque,
+            MaterialAlphaMode::Mask(cutoff) => AlphaMode::Mask(cutoff),
+            MaterialAlphaMode::Blend => AlphaMode::Blend,
+        }
+    }
+}
+
+/// Material of a brush texture, read from an optional `<texture>.material.ron` like
+/// `(metallic: 1.0, perceptual_roughness: 0.3, emissive_texture: Some("light_1_glow.png"))`.
+/// Missing fields keep the defaults, texture paths are relative to the definition.
+#[derive(Clone, Debug, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "0e6b1f3a-8c2d-4f7e-b5a9-3d4c7e1f2a60"]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialDefinition {
+    /// Multiplied with the texture
+    pub base_color: Color,
+    pub metallic: f32,
+    pub perceptual_roughness: f32,
+    pub reflectance: f32,
+    pub emissive: Color,
+    pub alpha_mode: MaterialAlphaMode,
+    pub unlit: bool,
+    /// Render the back of the faces too
+    pub double_sided: bool,
+    pub emissive_texture: Option<String>,
+    pub normal_map_texture: Option<String>,
+    pub metallic_roughness_texture: Option<String>,
+    pub occlusion_texture: Option<String>,
+}
+
+impl Default for MaterialDefinition {
+    fn default() -> Self {
+        MaterialDefinition {
+            base_color: Color::WHITE,
+            metallic: 0.0,
+            perceptual_roughness: 1.0,
+            reflectance: 0.0,
+            emissive: Color::BLACK,
+            alpha_mode: MaterialAlphaMode::Opaque,
+            unlit: false,
+            double_sided: false,
+            emissive_texture: None,
+            normal_map_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+        }
+    }
+}
+
+impl MaterialDefinition {
+    /// Makes texture paths relative to the assets folder instead of `directory`
+    fn resolve_textures(&mut self, directory: &Path) {
+        for texture in [
+            &mut self.emissive_texture,
+            &mut self.normal_map_texture,
+            &mut self.metallic_roughness_texture,
+            &mut self.occlusion_texture,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *texture = asset_path(&directory.join(&texture));
+        }
+    }
+
+    /// `image` turns a texture path into a handle
+    pub fn standard_material(
+        &self,
+        base_color_texture: Option<Handle<Image>>,
+        mut image: impl FnMut(&str) -> Handle<Image>,
+    ) -> StandardMaterial {
+        let mut image = |path: &Option<String>| path.as_deref().map(&mut image);
+        StandardMaterial {
+            base_color: self.base_color,
+            base_color_texture,
+            emissive: self.emissive,
+            emissive_texture: image(&self.emissive_texture),
+            perceptual_roughness: self.perceptual_roughness,
+            metallic: self.metallic,
+            metallic_roughness_texture: image(&self.metallic_roughness_texture),
+            reflectance: self.reflectance,
+            normal_map_texture: image(&self.normal_map_texture),
+            occlusion_texture: image(&self.occlusion_texture),
+            double_sided: self.double_sided,
+            cull_mode: match self.double_sided {
+                true => None,
+                false => Some(Face::Back),
+            },
+            unlit: self.unlit,
+            alpha_mode: self.alpha_mode.into(),
+            ..default()
+        }
+    }
+}
+
+/// Path with `/` separators, like asset paths in the map
+fn asset_path(path: &Path) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.iter().filter_map(|part| part.to_str()) {
+        match part {
+            "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Material of a texture used by the map
+#[derive(Clone, Debug, Default)]
+pub struct TextureMaterial {
+    pub definition: MaterialDefinition,
+    /// The texture's image in one of the map's WADs, used instead of its PNG
+    pub wad_image: Option<String>,
+    /// The definition file, if the texture has one
+    pub definition_path: Option<String>,
+    /// Maps found next to the texture by their suffix
+    pub paired: Vec<TextureMap>,
+    /// The texture's PNG, decoded when the map loader read its size
+    pub image: Option<Image>,
+    /// Paired maps that hold data instead of colors. The asset server would read them as sRGB,
+    /// so the map loader decodes them itself.
+    pub linear_maps: LinearMaps,
+    /// Frames, if the texture is part of a `+0name` sequence
+    pub animation: Option<TextureAnimation>,
+}
+
+impl TextureMaterial {
+    /// Adds the decoded images to the map as labeled images. Called once per texture,
+    /// every material of the texture shares the handles.
+    pub fn register_images(
+        &self,
+        load_context: &mut LoadContext,
+        texture: &str,
+    ) -> TextureImageHandles {
+        let mut register = |name: &str, image: &Option<Image>| {
+            image.as_ref().map(|image| {
+                load_context.set_labeled_asset(
+                    &format!("textures/{texture}/{name}"),
+                    LoadedAsset::new(image.clone()),
+                )
+            })
+        };
+        let maps = &self.linear_maps;
+        TextureImageHandles {
+            base_color: register("base_color", &self.image),
+            normal: register("normal", &maps.normal),
+            metallic_roughness: register("metallic_roughness", &maps.metallic_roughness),
+            occlusion: register("occlusion", &maps.occlusion),
+        }
+    }
+
+    /// Blended faces are drawn back to front, so they can't be merged into one mesh
+    pub fn is_blended(&self) -> bool {
+        self.definition.alpha_mode == MaterialAlphaMode::Blend
+    }
+
+    /// Blended and alpha tested faces can be seen through
+    pub fn is_opaque(&self) -> bool {
+        self.definition.alpha_mode == MaterialAlphaMode::Opaque
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LinearMaps {
+    pub normal: Option<Image>,
+    /// Roughness in green and metallic in blue
+    pub metallic_roughness: Option<Image>,
+    pub occlusion: Option<Image>,
+}
+
+/// Images a `TextureMaterial` decoded itself, after they were added to the map
+#[derive(Clone, Debug, Default)]
+pub struct TextureImageHandles {
+    pub base_color: Option<Handle<Image>>,
+    pub normal: Option<Handle<Image>>,
+    pub metallic_roughness: Option<Handle<Image>>,
+    pub occlusion: Option<Handle<Image>>,
+}
+
+/// Texture maps paired with a face texture by filename suffix, like `light_1_emission.png`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TextureMap {
+    Emission,
+    Normal,
+    Roughness,
+    Metallic,
+    Occlusion,
+}
+
+impl TextureMap {
+    pub const ALL: [TextureMap; 5] = [
+        TextureMap::Emission,
+        TextureMap::Normal,
+        TextureMap::Roughness,
+        TextureMap::Metallic,
+        TextureMap::Occlusion,
+    ];
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            TextureMap::Emission => "_emission",
+            TextureMap::Normal => "_normal",
+            TextureMap::Roughness => "_roughness",
+            TextureMap::Metallic => "_metallic",
+            TextureMap::Occlusion => "_occlusion",
+        }
+    }
+
+    /// Image path of the map for a face texture
+    pub fn path(&self, texture: &str) -> String {
+        texture_image_path(&format!("{texture}{}", self.suffix()))
+    }
+}
+
+impl Display for TextureMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.suffix()[1..])
+    }
+}
+
+/// Path of the material definition of a face texture
+pub fn material_definition_path(texture: &str) -> String {
+    format!("textures/{texture}.{MATERIAL_EXTENSION}")
+}
+
+/// Read the material definition of every texture referenced by the map and pair its maps.
+/// Textures without one, or with one that can't be read, get the default material.
+/// `images` are the PNGs decoded by `load_texture_images`.
+pub async fn load_texture_materials<'a>(
+    map: &Map,
+    wads: &MapWads,
+    mut images: HashMap<String, Image>,
+    glass_textures: &[String],
+    load_context: &LoadContext<'a>,
+) -> HashMap<String, TextureMaterial> {
+    let mut materials = HashMap::new();
+    for texture in map_textures(map) {
+        let path = material_definition_path(texture);
+        let definition = match load_context.read_asset_bytes(&path).await {
+            Ok(bytes) => parse_definition(&bytes, &path),
+            Err(AssetIoError::NotFound(_)) => None,
+            Err(err) => {
+                warn!("Could not read {path}, using the default material: {err}");
+                None
+            }
+        };
+        let mut material = match definition {
+            Some(definition) => TextureMaterial {
+                definition,
+                definition_path: Some(path),
+                ..default()
+            },
+            None => TextureMaterial::default(),
+        };
+        if material.definition.alpha_mode == MaterialAlphaMode::Opaque {
+            if let Some(alpha_mode) = texture_alpha_mode(texture, glass_textures) {
+                material.definition.alpha_mode = alpha_mode;
+            }
+        }
+        pair_texture_maps(texture, &mut material, load_context).await;
+        material.image = images.remove(texture);
+        material.wad_image = wads.image_path(texture);
+        material.animation = TextureAnimation::load(texture, wads, load_context);
+        materials.insert(texture.to_string(), material);
+    }
+    materials
+}
+
+/// Alpha mode from the texture name, for textures without one in their definition:
+/// names starting with `{` are alpha tested like in Quake, `glass_textures` are blended
+pub fn texture_alpha_mode(texture: &str, glass_textures: &[String]) -> Option<MaterialAlphaMode> {
+    let name = texture.rsplit('/').next().unwrap_or(texture);
+    if name.starts_with('{') {
+        return Some(MaterialAlphaMode::Mask(0.5));
+    }
+    glass_textures
+        .iter()
+        .any(|glass| glass.eq_ignore_ascii_case(texture))
+        .then_some(MaterialAlphaMode::Blend)
+}
+
+/// Fill the material slots the definition leaves empty with the `TextureMap`s next to the texture.
+/// A paired emission map lights up in white and a metallic map sets the metallic factor to 1,
+/// unless the definition sets them.
+async fn pair_texture_maps<'a>(
+    texture: &str,
+    material: &mut TextureMaterial,
+    load_context: &LoadContext<'a>,
+) {
+    let definition = &mut material.definition;
+    let found = |map: TextureMap, slot: &Option<String>| {
+        let path = map.path(texture);
+        let exists = slot.is_none() && load_context.asset_io().is_file(Path::new(&path));
+        exists.then_some(path)
+    };
+
+    if let Some(path) = found(TextureMap::Emission, &definition.emissive_texture) {
+        definition.emissive_texture = Some(path);
+        if definition.emissive == Color::BLACK {
+            definition.emissive = Color::WHITE;
+        }
+        material.paired.push(TextureMap::Emission);
+    }
+
+    let read = |map: TextureMap, slot: &Option<String>, is_srgb: bool| {
+        let path = found(map, slot);
+        async move {
+            let path = path?;
+            match read_png(&path, is_srgb, load_context).await {
+                Ok(image) => image,
+                Err(err) => {
+                    warn!("Could not read {path}: {err}");
+                    None
+                }
+            }
+        }
+    };
+    let normal = read(TextureMap::Normal, &definition.normal_map_texture, false).await;
+    let occlusion = read(TextureMap::Occlusion, &definition.occlusion_texture, false).await;
+    // Only read to combine them, the bytes are the same either way
+    let roughness = read(
+        TextureMap::Roughness,
+        &definition.metallic_roughness_texture,
+        true,
+    )
+    .await;
+    let metallic = read(
+        TextureMap::Metallic,
+        &definition.metallic_roughness_texture,
+        true,
+    )
+    .await;
+
+    let maps = &mut material.linear_maps;
+    if let Some(normal) = normal {
+        maps.normal = Some(normal);
+        material.paired.push(TextureMap::Normal);
+    }
+    if roughness.is_some() || metallic.is_some() {
+        match metallic_roughness_map(roughness.as_ref(), metallic.as_ref()) {
+            Ok(image) => {
+                maps.metallic_roughness = Some(image);
+                material
+                    .paired
+                    .extend(roughness.map(|_| TextureMap::Roughness));
+                if metallic.is_some() {
+                    material.paired.push(TextureMap::Metallic);
+                    if definition.metallic == 0.0 {
+                        definition.metallic = 1.0;
+                    }
+                }
+            }
+            Err(err) => {
+                warn!("Could not combine the roughness and metallic maps of {texture}: {err}")
+            }
+        }
+    }
+    if let Some(occlusion) = occlusion {
+        maps.occlusion = Some(occlusion);
+        material.paired.push(TextureMap::Occlusion);
+    }
+}
+
+/// Combine grayscale roughness and metallic maps like glTF does, roughness in green and metallic
+/// in blue. A missing map is white, so the material's factor applies unchanged.
+fn metallic_roughness_map(
+    roughness: Option<&Image>,
+    metallic: Option<&Image>,
+) -> Result<Image, String> {
+    let gray = |image: Option<&Image>| -> Result<Option<(Extent3d, Vec<u8>)>, String> {
+        let image = match image {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+        let image = image
+            .convert(TextureFormat::Rgba8UnormSrgb)
+            .ok_or_else(|| format!("unsupported format {:?}", image.texture_descriptor.format))?;
+        let pixels = image.data.chunks_exact(4).map(|pixel| pixel[0]).collect();
+        Ok(Some((image.texture_descriptor.size, pixels)))
+    };
+    let roughness = gray(roughness)?;
+    let metallic = gray(metallic)?;
+    let size = match (&roughness, &metallic) {
+        (Some((roughness, _)), Some((metallic, _))) if roughness != metallic => {
+            return Err(format!(
+                "the maps are {}x{} and {}x{}",
+                roughness.width, roughness.height, metallic.width, metallic.height
+            ))
+        }
+        (Some((size, _)), _) | (_, Some((size, _))) => *size,
+        (None, None) => return Err("there are no maps".to_string()),
+    };
+
+    let pixel_count = (size.width * size.height) as usize;
+    let channel =
+        |map: Option<(Extent3d, Vec<u8>)>| map.map_or(vec![255; pixel_count], |(_, pixels)| pixels);
+    let data = channel(roughness)
+        .into_iter()
+        .zip(channel(metallic))
+        .flat_map(|(roughness, metallic)| [255, roughness, metallic, 255])
+        .collect();
+    Ok(Image::new(
+        size,
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+    ))
+}
+
+/// Which maps each texture got by filename suffix, for checking a texture set
+pub fn texture_map_report(materials: &HashMap<String, TextureMaterial>) -> String {
+    let mut textures: Vec<(&String, &TextureMaterial)> = materials
+        .iter()
+        .filter(|(_, material)| !material.paired.is_empty())
+        .collect();
+    textures.sort_by_key(|(texture, _)| *texture);
+    textures
+        .into_iter()
+        .map(|(texture, material)| {
+            let maps: Vec<String> = material.paired.iter().map(|map| map.to_string()).collect();
+            format!("{texture}: {}", maps.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn parse_definition(bytes: &[u8], path: &str) -> Option<MaterialDefinition> {
+    match ron::de::from_bytes::<MaterialDefinition>(bytes) {
+        Ok(mut definition) => {
+            definition.resolve_textures(Path::new(path).parent().unwrap_or(Path::new("")));
+            Some(definition)
+        }
+        Err(err) => {
+            warn!("Could not parse {path}, using the default material: {err}");
+            None
+        }
+    }
+}
+
+/// Loads material definitions as assets, so maps can depend on them
+#[derive(Default)]
+pub struct MaterialDefinitionLoader;
+
+impl AssetLoader for MaterialDefinitionLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let mut definition = ron::de::from_bytes::<MaterialDefinition>(bytes)?;
+            definition.resolve_textures(load_context.path().parent().unwrap_or(Path::new("")));
+            load_context.set_default_asset(LoadedAsset::new(definition));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[MATERIAL_EXTENSION]
+    }
+}
+
+/// Builds the material of a face texture, with the images it doesn't decode itself and the
+/// definition as dependencies. `images` come from `TextureMaterial::register_images`.
+pub fn texture_material(
+    load_context: &mut LoadContext,
+    texture: &str,
+    material: &TextureMaterial,
+    images: &TextureImageHandles,
+) -> LoadedAsset<StandardMaterial> {
+    let mut dependencies = vec![];
+    let base_color_texture = match &images.base_color {
+        Some(image) => image.clone(),
+        None => {
+            let path = material
+                .wad_image
+                .clone()
+                .unwrap_or_else(|| texture_image_path(texture));
+            let image = load_context.get_handle(path.as_str());
+            dependencies.push(path);
+            image
+        }
+    };
+    let mut standard_material =
+        material
+            .definition
+            .standard_material(Some(base_color_texture), |path: &str| {
+                dependencies.push(path.to_string());
+                load_context.get_handle(path)
+            });
+    dependencies.extend(material.definition_path.iter().cloned());
+    dependencies.extend(
+        material
+            .animation
+            .iter()
+            .flat_map(|animation| animation.paths().cloned()),
+    );
+    standard_material.normal_map_texture = standard_material
+        .normal_map_texture
+        .or_else(|| images.normal.clone());
+    standard_material.metallic_roughness_texture = standard_material
+        .metallic_roughness_texture
+        .or_else(|| images.metallic_roughness.clone());
+    standard_material.occlusion_texture = standard_material
+        .occlusion_texture
+        .or_else(|| images.occlusion.clone());
+
+    let mut asset = LoadedAsset::new(standard_material);
+    for path in dependencies {
+        asset = asset.with_dependency(path.as_str().into());
+    }
+    asset
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use std::collections::HashMap;
+
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::{
+        metallic_roughness_map, parse_definition, texture_alpha_mode, texture_map_report,
+        MaterialAlphaMode, MaterialDefinition, TextureMap, TextureMaterial,
+    };
+
+    #[test]
+    fn material_definition() {
+        let definition = parse_definition(
+            br#"(
+                metallic: 1.0,
+                emissive: Rgba(red: 1.0, green: 0.5, blue: 0.0, alpha: 1.0),
+                alpha_mode: Mask(0.5),
+                double_sided: true,
+                emissive_texture: Some("light_1_glow.png"),
+                normal_map_texture: Some("../normal.png"),
+            )"#,
+            "textures/station/light_1.material.ron",
+        )
+        .unwrap();
+        assert_eq!(
+            MaterialDefinition {
+                metallic: 1.0,
+                emissive: Color::rgb(1.0, 0.5, 0.0),
+                alpha_mode: MaterialAlphaMode::Mask(0.5),
+                double_sided: true,
+                emissive_texture: Some("textures/station/light_1_glow.png".to_string()),
+                normal_map_texture: Some("textures/normal.png".to_string()),
+                ..default()
+            },
+            definition
+        );
+
+        let material = definition.standard_material(None, |_| Handle::default());
+        assert_eq!(AlphaMode::Mask(0.5), material.alpha_mode);
+        assert_eq!(None, material.cull_mode);
+        assert!(material.emissive_texture.is_some());
+        assert!(material.occlusion_texture.is_none());
+        assert_eq!(1.0, material.perceptual_roughness);
+    }
+
+    #[test]
+    fn invalid_material_definition() {
+        let path = "textures/station/wall_1.material.ron";
+        assert_eq!(None, parse_definition(b"(shininess: 2.0)", path));
+        assert_eq!(None, parse_definition(b"not ron", path));
+    }
+
+    fn gray(size: u32, value: u8) -> Image {
+        Image::new(
+            Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            [value, value, value, 255].repeat((size * size) as usize),
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    #[test]
+    fn combined_metallic_roughness() {
+        let image = metallic_roughness_map(Some(&gray(2, 100)), Some(&gray(2, 50))).unwrap();
+        assert_eq!(TextureFormat::Rgba8Unorm, image.texture_descriptor.format);
+        assert_eq!([255, 100, 50, 255].repeat(4), image.data);
+
+        // The missing metallic map leaves the metallic factor as it is
+        let image = metallic_roughness_map(Some(&gray(1, 100)), None).unwrap();
+        assert_eq!(vec![255, 100, 255, 255], image.data);
+
+        assert!(metallic_roughness_map(Some(&gray(2, 100)), Some(&gray(4, 50))).is_err());
+    }
+
+    #[test]
+    fn texture_maps() {
+        assert_eq!(
+            "textures/station/light_1_emission.png",
+            TextureMap::Emission.path("station/light_1")
+        );
+
+        let mut materials = HashMap::new();
+        materials.insert("station/wall_1".to_string(), TextureMaterial::default());
+        materials.insert(
+            "station/light_1".to_string(),
+            TextureMaterial {
+                paired: vec![TextureMap::Emission],
+                ..default()
+            },
+        );
+        materials.insert(
+            "station/floor_1".to_string(),
+            TextureMaterial {
+                paired: vec![TextureMap::Normal, TextureMap::Roughness],
+                ..default()
+            },
+        );
+        assert_eq!(
+            "station/floor_1: normal, roughness\nstation/light_1: emission",
+            texture_map_report(&materials)
+        );
+    }
+
+    #[test]
+    fn alpha_from_texture_name() {
+        let glass = ["station/glass_1".to_string()];
+        assert_eq!(
+            Some(MaterialAlphaMode::Mask(0.5)),
+            texture_alpha_mode("station/{grate_2", &glass)
+        );
+        assert_eq!(
+            Some(MaterialAlphaMode::Blend),
+            texture_alpha_mode("station/glass_1", &glass)
+        );
+        assert_eq!(None, texture_alpha_mode("station/glass_2", &glass));
+        assert_eq!(None, texture_alpha_mode("station/wall_{1", &glass));
+    }
+}
@@ -0,0 +1,425 @@
+use bevy::{prelude::*, utils::HashMap};
+
+pub use super::error::PropertyError;
+use super::{
+    component::parse_position,
+    fgd::{FgdChoice, FgdClass, FgdProperty, FgdPropertyKind},
+};
+pub use epsilon_derive::FromMapProperties;
+
+/// Typed view of an entity's key-value properties, usually derived:
+///
+/// ```ignore
+/// #[derive(FromMapProperties)]
+/// struct LightPoint {
+///     #[map(default = 800.0)]
+///     intensity: f32,
+///     #[map(rename = "_color", default = "Color::WHITE")]
+///     color: Color,
+///     target: Option<EntityRef>,
+/// }
+/// ```
+pub trait FromMapProperties: Sized {
+    /// Reads every field, reporting all fields that are missing or invalid
+    fn from_map_properties(
+        properties: &HashMap<String, String>,
+    ) -> Result<Self, Vec<PropertyError>>;
+
+    /// FGD definitions of the fields, with their defaults
+    fn fgd_properties() -> Vec<FgdProperty> {
+        vec![]
+    }
+}
+
+/// Properties of a map entity class that can be exported to the FGD, usually derived with
+/// `#[map(point_class = "light_point", description = "Point light")]` on the struct
+pub trait MapEntityClass: FromMapProperties {
+    const CLASSNAME: &'static str;
+
+    fn fgd_class() -> FgdClass;
+}
+
+/// A single property value
+pub trait FromMapValue: Sized {
+    /// Used in error messages, e.g. "a number"
+    const EXPECTED: &'static str;
+
+    fn from_map_value(value: &str) -> Option<Self>;
+
+    /// Property type written to the FGD
+    fn fgd_kind() -> FgdPropertyKind {
+        FgdPropertyKind::String
+    }
+}
+
+/// Writes a value the way it's read by `FromMapValue`, used for FGD defaults
+pub trait ToMapValue {
+    fn to_map_value(&self) -> String;
+}
+
+/// Parse `key` if the entity has it
+pub fn field<T: FromMapValue>(
+    properties: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>, PropertyError> {
+    let value = match properties.get(key) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    T::from_map_value(value)
+        .map(Some)
+        .ok_or_else(|| PropertyError::Invalid {
+            key: key.to_string(),
+            value: value.to_string(),
+            expected: T::EXPECTED,
+        })
+}
+
+/// Bit field of the `spawnflags` key
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpawnFlags(pub u32);
+
+impl SpawnFlags {
+    pub fn contains(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+}
+
+/// Name of another entity, matched against its `targetname`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct EntityRef(pub String);
+
+macro_rules! impl_from_str_value {
+    ($expected:literal, $kind:ident, $($ty:ty),*) => {
+        $(
+            impl FromMapValue for $ty {
+                const EXPECTED: &'static str = $expected;
+
+                fn from_map_value(value: &str) -> Option<Self> {
+                    value.trim().parse().ok()
+                }
+
+                fn fgd_kind() -> FgdPropertyKind {
+                    FgdPropertyKind::$kind
+                }
+            }
+
+            impl ToMapValue for $ty {
+                fn to_map_value(&self) -> String {
+                    self.to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_from_str_value!("a number", Float, f32, f64);
+impl_from_str_value!(
+    "an integer",
+    Integer,
+    i8,
+    i16,
+    i32,
+    i64,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    usize
+);
+
+impl FromMapValue for String {
+    const EXPECTED: &'static str = "a string";
+
+    fn from_map_value(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+impl ToMapValue for String {
+    fn to_map_value(&self) -> String {
+        self.clone()
+    }
+}
+
+impl FromMapValue for bool {
+    const EXPECTED: &'static str = "0 or 1";
+
+    fn from_map_value(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "1" | "true" => Some(true),
+            "0" | "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn fgd_kind() -> FgdPropertyKind {
+        FgdPropertyKind::Choices(vec![
+            FgdChoice {
+                value: "0".to_string(),
+                name: "No".to_string(),
+            },
+            FgdChoice {
+                value: "1".to_string(),
+                name: "Yes".to_string(),
+            },
+        ])
+    }
+}
+
+impl ToMapValue for bool {
+    fn to_map_value(&self) -> String {
+        (*self as u8).to_string()
+    }
+}
+
+/// Position in map coordinates, no conversion is applied
+impl FromMapValue for Vec3 {
+    const EXPECTED: &'static str = "three numbers";
+
+    fn from_map_value(value: &str) -> Option<Self> {
+        parse_position(value)
+    }
+}
+
+impl ToMapValue for Vec3 {
+    fn to_map_value(&self) -> String {
+        format!("{} {} {}", self.x, self.y, self.z)
+    }
+}
+
+/// `r g b` or `r g b a` from 0 to 255, like `_color` in Quake and `color255` in the FGD
+impl FromMapValue for Color {
+    const EXPECTED: &'static str = "a color";
+
+    fn from_map_value(value: &str) -> Option<Self> {
+        let components = value
+            .split_ascii_whitespace()
+            .map(|component| component.parse::<f32>().ok())
+            .collect::<Option<Vec<f32>>>()?;
+        if !(3..=4).contains(&components.len())
+            || components
+                .iter()
+                .any(|component| !(0.0..=255.0).contains(component))
+        {
+            return None;
+        }
+        let alpha = components.get(3).copied().unwrap_or(255.0);
+        Some(Color::rgba(
+            components[0] / 255.0,
+            components[1] / 255.0,
+            components[2] / 255.0,
+            alpha / 255.0,
+        ))
+    }
+
+    fn fgd_kind() -> FgdPropertyKind {
+        FgdPropertyKind::Color255
+    }
+}
+
+/// `r g b` from 0 to 255, with alpha only if it isn't opaque
+impl ToMapValue for Color {
+    fn to_map_value(&self) -> String {
+        let [r, g, b, a] = self.as_rgba_f32().map(|c| (c * 255.0).round() as u8);
+        match a {
+            255 => format!("{r} {g} {b}"),
+            _ => format!("{r} {g} {b} {a}"),
+        }
+    }
+}
+
+impl FromMapValue for SpawnFlags {
+    const EXPECTED: &'static str = "an integer";
+
+    fn from_map_value(value: &str) -> Option<Self> {
+        u32::from_map_value(value).map(SpawnFlags)
+    }
+
+    /// Flag names are only known to the entity, so they're left out
+    fn fgd_kind() -> FgdPropertyKind {
+        FgdPropertyKind::Flags(vec![])
+    }
+}
+
+impl ToMapValue for SpawnFlags {
+    fn to_map_value(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl FromMapValue for EntityRef {
+    const EXPECTED: &'static str = "an entity name";
+
+    fn from_map_value(value: &str) -> Option<Self> {
+        let name = value.trim();
+        (!name.is_empty()).then(|| EntityRef(name.to_string()))
+    }
+
+    fn fgd_kind() -> FgdPropertyKind {
+        FgdPropertyKind::TargetDestination
+    }
+}
+
+impl ToMapValue for EntityRef {
+    fn to_map_value(&self) -> String {
+        self.0.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{EntityRef, FromMapProperties, FromMapValue, PropertyError, SpawnFlags};
+    use crate::qmap::{fgd::FgdPropertyKind, test_util::properties};
+
+    #[derive(FromMapProperties, Debug, PartialEq)]
+    struct Light {
+        #[map(default = 800.0)]
+        intensity: f32,
+        #[map(rename = "_color", default = "Color::WHITE")]
+        color: Color,
+        #[map(default)]
+        spawnflags: SpawnFlags,
+        #[map(default = true)]
+        shadows: bool,
+        origin: Vec3,
+        delay: Option<u32>,
+        target: Option<EntityRef>,
+    }
+
+    #[test]
+    fn typed_fields() {
+        let light = Light::from_map_properties(&properties(&[
+            ("origin", "16 -32 64"),
+            ("_color", "255 0 51"),
+            ("spawnflags", "5"),
+            ("shadows", "0"),
+            ("delay", "2"),
+            ("target", "door1"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            Light {
+                intensity: 800.0,
+                color: Color::rgba(1.0, 0.0, 0.2, 1.0),
+                spawnflags: SpawnFlags(5),
+                shadows: false,
+                origin: Vec3::new(16.0, -32.0, 64.0),
+                delay: Some(2),
+                target: Some(EntityRef("door1".to_string())),
+            },
+            light
+        );
+        assert!(light.spawnflags.contains(4));
+        assert!(!light.spawnflags.contains(2));
+    }
+
+    /// Derived with an explicit path to the crate with `qmap`
+    #[derive(FromMapProperties, Debug, PartialEq)]
+    #[map(crate = "crate")]
+    struct Relay {
+        #[map(default)]
+        delay: f32,
+    }
+
+    #[test]
+    fn crate_root() {
+        assert_eq!(
+            Ok(Relay { delay: 0.5 }),
+            Relay::from_map_properties(&properties(&[("delay", "0.5")]))
+        );
+    }
+
+    #[test]
+    fn defaults() {
+        let light = Light::from_map_properties(&properties(&[("origin", "0 0 0")])).unwrap();
+        assert_eq!(800.0, light.intensity);
+        assert_eq!(Color::WHITE, light.color);
+        assert_eq!(SpawnFlags(0), light.spawnflags);
+        assert!(light.shadows);
+        assert_eq!(None, light.delay);
+        assert_eq!(None, light.target);
+    }
+
+    #[test]
+    fn errors_for_each_field() {
+        let errors = Light::from_map_properties(&properties(&[
+            ("intensity", "bright"),
+            ("_color", "1 2"),
+            ("delay", "-1"),
+        ]))
+        .unwrap_err();
+        assert_eq!(
+            vec![
+                PropertyError::Invalid {
+                    key: "intensity".to_string(),
+                    value: "bright".to_string(),
+                    expected: "a number",
+                },
+                PropertyError::Invalid {
+                    key: "_color".to_string(),
+                    value: "1 2".to_string(),
+                    expected: "a color",
+                },
+                PropertyError::Missing {
+                    key: "origin".to_string(),
+                },
+                PropertyError::Invalid {
+                    key: "delay".to_string(),
+                    value: "-1".to_string(),
+                    expected: "an integer",
+                },
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn fgd_properties() {
+        let properties = Light::fgd_properties();
+        let defaults: Vec<(&str, Option<&str>)> = properties
+            .iter()
+            .map(|property| (property.name.as_str(), property.default.as_deref()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("intensity", Some("800")),
+                ("_color", Some("255 255 255")),
+                ("spawnflags", Some("0")),
+                ("shadows", Some("1")),
+                ("origin", None),
+                ("delay", None),
+                ("target", None),
+            ],
+            defaults
+        );
+        assert_eq!("Color", properties[1].display_name);
+        assert_eq!(FgdPropertyKind::Color255, properties[1].kind);
+        assert_eq!(FgdPropertyKind::TargetDestination, properties[6].kind);
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(
+            Some(Color::rgba(0.2, 0.0, 1.0, 1.0)),
+            Color::from_map_value("51 0 255")
+        );
+        // Always 0-255, even if every component is at most 1
+        assert_eq!(
+            Some(Color::rgba(1.0 / 255.0, 1.0 / 255.0, 1.0 / 255.0, 1.0)),
+            Color::from_map_value("1 1 1")
+        );
+        assert_eq!(
+            Some(Color::rgba(1.0, 1.0, 1.0, 0.2)),
+            Color::from_map_value("255 255 255 51")
+        );
+        assert_eq!(None, Color::from_map_value("255 255"));
+        assert_eq!(None, Color::from_map_value("256 0 0"));
+        assert_eq!(None, Color::from_map_value("red"));
+        assert_eq!(None, bool::from_map_value("yes"));
+        assert_eq!(None, EntityRef::from_map_value("  "));
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+
+use super::{
+    component::MapPointEntity,
+    fgd::{Fgd, FgdClass},
+    properties::MapEntityClass,
+};
+
+/// Called once for every new map point entity with a matching classname
+pub type MapEntitySpawner = Box<dyn Fn(&mut Commands, Entity, &MapPointEntity) + Send + Sync>;
+
+/// Spawners for map point entities by classname, see `MapEntityAppExt::register_map_entity`
+#[derive(Default)]
+pub struct MapEntityRegistry {
+    spawners: HashMap<String, MapEntitySpawner>,
+    /// Definitions exported to the FGD, in registration order
+    classes: Vec<FgdClass>,
+    /// Unknown classnames that have already been reported, per map
+    reported: HashSet<(Option<Entity>, String)>,
+}
+
+impl MapEntityRegistry {
+    pub fn register(
+        &mut self,
+        classname: &str,
+        spawner: impl Fn(&mut Commands, Entity, &MapPointEntity) + Send + Sync + 'static,
+    ) {
+        if self
+            .spawners
+            .insert(classname.to_string(), Box::new(spawner))
+            .is_some()
+        {
+            warn!("Replacing the spawner of map entity {classname}");
+        }
+    }
+
+    pub fn is_registered(&self, classname: &str) -> bool {
+        self.spawners.contains_key(classname)
+    }
+
+    /// Add a class to the exported FGD, replacing one with the same name
+    pub fn register_class(&mut self, class: FgdClass) {
+        match self
+            .classes
+            .iter_mut()
+            .find(|existing| existing.name == class.name)
+        {
+            Some(existing) => *existing = class,
+            None => self.classes.push(class),
+        }
+    }
+
+    /// Definitions of every registered class, written with `epsilon --export-fgd`
+    pub fn fgd(&self) -> Fgd {
+        Fgd {
+            classes: self.classes.clone(),
+        }
+    }
+}
+
+pub trait MapEntityAppExt {
+    /// Spawn `classname` entities of every loaded map with `spawner`
+    fn register_map_entity(
+        &mut self,
+        classname: &str,
+        spawner: impl Fn(&mut Commands, Entity, &MapPointEntity) + Send + Sync + 'static,
+    ) -> &mut Self;
+
+    /// Spawn `T::CLASSNAME` entities with their parsed properties and export `T` to the FGD.
+    /// Entities with invalid properties are reported and skipped.
+    fn register_map_class<T: MapEntityClass>(
+        &mut self,
+        spawner: impl Fn(&mut Commands, Entity, &MapPointEntity, T) + Send + Sync + 'static,
+    ) -> &mut Self;
+
+    /// Export a class without a spawner, like worldspawn or base classes
+    fn register_fgd_class(&mut self, class: FgdClass) -> &mut Self;
+}
+
+impl MapEntityAppExt for App {
+    fn register_map_entity(
+        &mut self,
+        classname: &str,
+        spawner: impl Fn(&mut Commands, Entity, &MapPointEntity) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.init_resource::<MapEntityRegistry>();
+        self.world
+            .resource_mut::<MapEntityRegistry>()
+            .register(classname, spawner);
+        self
+    }
+
+    fn register_map_class<T: MapEntityClass>(
+        &mut self,
+        spawner: impl Fn(&mut Commands, Entity, &MapPointEntity, T) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.register_fgd_class(T::fgd_class());
+        self.register_map_entity(
+            T::CLASSNAME,
+            move |commands, entity, map_entity| match map_entity.parse_properties::<T>() {
+                Ok(properties) => spawner(commands, entity, map_entity, properties),
+                Err(errors) => {
+                    for err in errors {
+                        warn!("Skipping {}: {err}", map_entity.name);
+                    }
+                }
+            },
+        )
+    }
+
+    fn register_fgd_class(&mut self, class: FgdClass) -> &mut Self {
+        self.init_resource::<MapEntityRegistry>();
+        self.world
+            .resource_mut::<MapEntityRegistry>()
+            .register_class(class);
+        self
+    }
+}
+
+pub fn map_entity_dispatcher(
+    mut commands: Commands,
+    mut registry: ResMut<MapEntityRegistry>,
+    query: Query<(Entity, &MapPointEntity, Option<&Parent>), Added<MapPointEntity>>,
+    entities: Query<Entity>,
+) {
+    // Forget the reports of maps that have been despawned
+    if !registry.reported.is_empty() {
+        registry
+            .reported
+            .retain(|(map, _)| map.map_or(true, |map| entities.contains(map)));
+    }
+
+    for (entity, map_entity, parent) in query.iter() {
+        if let Some(spawner) = registry.spawners.get(&map_entity.name) {
+            spawner(&mut commands, entity, map_entity);
+            continue;
+        }
+        // Every map has a worldspawn, its keys are applied by `apply_worldspawn`
+        if map_entity.name == "worldspawn" {
+            continue;
+        }
+        // Point entities are children of the map root
+        let map = parent.map(|parent| parent.get());
+        if registry.reported.insert((map, map_entity.name.clone())) {
+            warn!(
+                "No spawner registered for map entity {}, ignoring it",
+                map_entity.name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{map_entity_dispatcher, MapEntityAppExt, MapEntityRegistry};
+    use crate::qmap::component::MapPointEntity;
+
+    #[derive(Component)]
+    struct Spawned;
+
+    fn map_with_entities(app: &mut App, names: &[&str]) -> Entity {
+        let children: Vec<Entity> = names
+            .iter()
+            .map(|name| {
+                app.world
+                    .spawn()
+                    .insert(MapPointEntity {
+                        name: name.to_string(),
+                        ..default()
+                    })
+                    .id()
+            })
+            .collect();
+        app.world.spawn().push_children(&children).id()
+    }
+
+    #[test]
+    fn dispatch_by_classname() {
+        let mut app = App::new();
+        app.register_map_entity("light_point", |commands, entity, _| {
+            commands.entity(entity).insert(Spawned);
+        })
+        .add_system(map_entity_dispatcher);
+
+        let map = map_with_entities(&mut app, &["light_point", "info_null", "info_null"]);
+        app.update();
+        app.update();
+
+        let mut spawned = app.world.query_filtered::<&MapPointEntity, With<Spawned>>();
+        let names: Vec<_> = spawned
+            .iter(&app.world)
+            .map(|entity| entity.name.clone())
+            .collect();
+        assert_eq!(vec!["light_point".to_string()], names);
+
+        let registry = app.world.resource::<MapEntityRegistry>();
+        assert!(registry.is_registered("light_point"));
+        assert_eq!(1, registry.reported.len());
+        assert!(registry
+            .reported
+            .contains(&(Some(map), "info_null".to_string())));
+
+        // Every map reports its own unknown classnames
+        let other_map = map_with_entities(&mut app, &["info_null"]);
+        app.update();
+        assert_eq!(2, app.world.resource::<MapEntityRegistry>().reported.len());
+
+        // Reports are forgotten with their map
+        despawn_with_children_recursive(&mut app.world, other_map);
+        app.update();
+        let registry = app.world.resource::<MapEntityRegistry>();
+        assert_eq!(1, registry.reported.len());
+        assert!(registry
+            .reported
+            .contains(&(Some(map), "info_null".to_string())));
+    }
+
+    #[test]
+    fn worldspawn_is_not_reported() {
+        let mut app = App::new();
+        app.init_resource::<MapEntityRegistry>()
+            .add_system(map_entity_dispatcher);
+
+        map_with_entities(&mut app, &["worldspawn"]);
+        app.update();
+
+        assert!(app
+            .world
+            .resource::<MapEntityRegistry>()
+            .reported
+            .is_empty());
+    }
+}
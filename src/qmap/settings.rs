@@ -0,0 +1,183 @@
+use bevy::{prelude::*, utils::HashMap};
+
+/// Loader settings, insert the resource before adding `QMapPlugin` to override the defaults
+#[derive(Clone)]
+pub struct QMapSettings {
+    pub mesh_mode: MeshMode,
+    /// Remove worldspawn faces that are hidden inside or against other worldspawn brushes
+    pub cull_hidden_faces: bool,
+    /// Default collider shape, maps and brush entities can override it with the `_collider` key
+    pub collider_mode: ColliderMode,
+    /// Entity definitions used to fill in defaults and check point entity properties,
+    /// relative to the assets folder
+    pub fgd_path: Option<String>,
+    /// Default scale and axes, maps can override them with the `_scale` and `_up_axis` keys
+    pub conversion: MapConversion,
+    /// Textures drawn with alpha blending, like `station/glass_1`. Textures starting with `{`
+    /// are alpha tested, material definitions can set either.
+    pub glass_textures: Vec<String>,
+}
+
+impl Default for QMapSettings {
+    fn default() -> Self {
+        QMapSettings {
+            mesh_mode: MeshMode::default(),
+            cull_hidden_faces: true,
+            collider_mode: ColliderMode::default(),
+            fgd_path: Some("entity.fgd".to_string()),
+            conversion: MapConversion::default(),
+            glass_textures: vec![],
+        }
+    }
+}
+
+/// How brush faces are turned into meshes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshMode {
+    /// One mesh and entity per face, useful for debugging
+    PerFace,
+    /// One mesh per texture for each brush entity (worldspawn included)
+    #[default]
+    Merged,
+}
+
+/// Entity key that overrides `QMapSettings::collider_mode`, on worldspawn it applies to the world brushes
+pub const COLLIDER_MODE_KEY: &str = "_collider";
+
+/// How brush colliders are built. Trigger brushes are always convex sensors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColliderMode {
+    /// One convex hull collider for each brush
+    #[default]
+    ConvexPerBrush,
+    /// One compound collider of convex hulls for each brush entity (worldspawn included)
+    Compound,
+    /// One triangle mesh collider of the visible faces for each brush entity
+    Trimesh,
+}
+
+impl ColliderMode {
+    /// Parse the `_collider` key: `convex`, `compound` or `trimesh`
+    pub fn from_property(value: &str) -> Option<Self> {
+        match value.trim() {
+            "convex" => Some(ColliderMode::ConvexPerBrush),
+            "compound" => Some(ColliderMode::Compound),
+            "trimesh" => Some(ColliderMode::Trimesh),
+            _ => None,
+        }
+    }
+}
+
+/// Worldspawn key that overrides `MapConversion::scale`
+pub const SCALE_KEY: &str = "_scale";
+/// Worldspawn key that overrides `MapConversion::z_up`, `z` or `y`
+pub const UP_AXIS_KEY: &str = "_up_axis";
+
+/// How map coordinates become world coordinates.
+/// The map root carries the conversion that was used to load it.
+#[derive(Clone, Copy, Debug, PartialEq, Component, Reflect)]
+#[reflect(Component)]
+pub struct MapConversion {
+    /// World units per map unit
+    pub scale: f32,
+    /// The map's up axis is Z like in Quake, otherwise it's already Y like in Bevy
+    pub z_up: bool,
+}
+
+impl Default for MapConversion {
+    fn default() -> Self {
+        MapConversion {
+            // 16 map units per world unit
+            scale: 1.0 / 16.0,
+            z_up: true,
+        }
+    }
+}
+
+impl MapConversion {
+    /// Map position to world position
+    pub fn to_world(&self, point: Vec3) -> Vec3 {
+        self.to_world_direction(point) * self.scale
+    }
+
+    /// Inverse of `to_world`
+    pub fn to_map(&self, point: Vec3) -> Vec3 {
+        self.to_map_direction(point / self.scale)
+    }
+
+    /// Rotates a map direction or normal into world axes, without scaling it
+    pub fn to_world_direction(&self, direction: Vec3) -> Vec3 {
+        match self.z_up {
+            true => Vec3::new(direction.x, direction.z, -direction.y),
+            false => direction,
+        }
+    }
+
+    /// Inverse of `to_world_direction`
+    pub fn to_map_direction(&self, direction: Vec3) -> Vec3 {
+        match self.z_up {
+            true => Vec3::new(direction.x, -direction.z, direction.y),
+            false => direction,
+        }
+    }
+
+    /// Map distance, like a light range, in world units
+    pub fn to_world_length(&self, length: f32) -> f32 {
+        length * self.scale
+    }
+
+    /// Apply the `_scale` and `_up_axis` keys of the worldspawn.
+    /// Invalid values are returned as `(key, value)` and leave the setting unchanged.
+    pub fn with_properties(
+        mut self,
+        properties: &HashMap<String, String>,
+    ) -> (Self, Vec<(&'static str, String)>) {
+        let mut invalid = vec![];
+        if let Some(value) = properties.get(SCALE_KEY) {
+            match value.trim().parse::<f32>() {
+                Ok(scale) if scale > 0.0 && scale.is_finite() => self.scale = scale,
+                _ => invalid.push((SCALE_KEY, value.clone())),
+            }
+        }
+        if let Some(value) = properties.get(UP_AXIS_KEY) {
+            match value.trim().to_lowercase().as_str() {
+                "z" => self.z_up = true,
+                "y" => self.z_up = false,
+                _ => invalid.push((UP_AXIS_KEY, value.clone())),
+            }
+        }
+        (self, invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{MapConversion, SCALE_KEY, UP_AXIS_KEY};
+    use crate::qmap::test_util::properties;
+
+    #[test]
+    fn conversion() {
+        let quake = MapConversion::default();
+        let point = Vec3::new(16.0, 32.0, 48.0);
+        assert_eq!(Vec3::new(1.0, 3.0, -2.0), quake.to_world(point));
+        assert_eq!(point, quake.to_map(quake.to_world(point)));
+        assert_eq!(Vec3::Y, quake.to_world_direction(Vec3::Z));
+
+        let (conversion, invalid) =
+            quake.with_properties(&properties(&[(SCALE_KEY, "0.5"), (UP_AXIS_KEY, "Y")]));
+        assert!(invalid.is_empty());
+        assert_eq!(Vec3::new(8.0, 16.0, 24.0), conversion.to_world(point));
+        assert_eq!(point, conversion.to_map(conversion.to_world(point)));
+        assert_eq!(4.0, conversion.to_world_length(8.0));
+
+        let (conversion, invalid) =
+            quake.with_properties(&properties(&[(SCALE_KEY, "-1"), (UP_AXIS_KEY, "x")]));
+        assert_eq!(quake, conversion);
+        assert_eq!(
+            vec![SCALE_KEY, UP_AXIS_KEY],
+            invalid.iter().map(|(key, _)| *key).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use std::f32::consts::PI;
+
+use bevy::{
+    asset::LoadContext,
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{
+        AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat, TextureViewDescriptor,
+        TextureViewDimension,
+    },
+    utils::HashMap,
+};
+
+use super::{
+    component::SkySurface, error::SkyError, map_component, settings::MapConversion,
+    texture::read_png,
+};
+
+/// Sky images are looked up here by the worldspawn `sky` key
+pub const SKY_DIR: &str = "textures/sky";
+
+const SKY_SHADER: &str = "shaders/sky.wgsl";
+
+/// Quake sky face suffixes with the map axes of the face, like `st_to_vec` in Quake 2:
+/// 1 and 2 are the horizontal and vertical image axis, 3 is the direction the face looks at
+const SKY_FACES: [(&str, [i8; 3]); 6] = [
+    ("rt", [3, -1, 2]),
+    ("lf", [-3, 1, 2]),
+    ("bk", [1, 3, 2]),
+    ("ft", [-1, -3, 2]),
+    ("up", [-2, -1, 3]),
+    ("dn", [2, -1, -3]),
+];
+
+/// Cubemap of the map's sky, on the map root
+#[derive(Default, Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct MapSky {
+    pub cubemap: Handle<Image>,
+}
+
+/// Draws the sky cubemap in the view direction, so sky faces look like openings to the sky
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "5b3c8a4e-2f61-4d0b-9a57-c1e0d8f3b6a2"]
+pub struct SkyMaterial {
+    #[texture(0, dimension = "cube")]
+    #[sampler(1)]
+    pub cubemap: Handle<Image>,
+}
+
+impl Material for SkyMaterial {
+    fn fragment_shader() -> ShaderRef {
+        SKY_SHADER.into()
+    }
+}
+
+/// RGBA pixels of a sky image
+struct SkyImage {
+    path: String,
+    size: UVec2,
+    data: Vec<u8>,
+}
+
+impl SkyImage {
+    fn from_image(path: String, image: &Image) -> Result<Self, SkyError> {
+        let image = image
+            .convert(TextureFormat::Rgba8UnormSrgb)
+            .ok_or_else(|| SkyError::Unreadable {
+                path: path.clone(),
+                message: format!(
+                    "unsupported texture format {:?}",
+                    image.texture_descriptor.format
+                ),
+            })?;
+        let size = image.texture_descriptor.size;
+        Ok(SkyImage {
+            path,
+            size: UVec2::new(size.width, size.height),
+            data: image.data,
+        })
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> [f32; 4] {
+        let index = (y.min(self.size.y - 1) * self.size.x + x.min(self.size.x - 1)) as usize * 4;
+        let pixel = &self.data[index..index + 4];
+        [0, 1, 2, 3].map(|channel| pixel[channel] as f32)
+    }
+
+    /// Texel closest to the UV coordinates
+    fn sample_nearest(&self, uv: Vec2) -> [f32; 4] {
+        let texel = (uv * self.size.as_vec2()).floor().as_uvec2();
+        self.pixel(texel.x, texel.y)
+    }
+
+    /// Bilinear sample that wraps around horizontally
+    fn sample_bilinear(&self, uv: Vec2) -> [f32; 4] {
+        let position = uv * self.size.as_vec2() - 0.5;
+        let base = position.floor();
+        let fraction = position - base;
+        let x0 = (base.x as i64).rem_euclid(self.size.x as i64) as u32;
+        let x1 = (x0 + 1) % self.size.x;
+        let y0 = base.y.max(0.0) as u32;
+        let y1 = y0 + 1;
+        let mut result = [0.0; 4];
+        for (x, y, weight) in [
+            (x0, y0, (1.0 - fraction.x) * (1.0 - fraction.y)),
+            (x1, y0, fraction.x * (1.0 - fraction.y)),
+            (x0, y1, (1.0 - fraction.x) * fraction.y),
+            (x1, y1, fraction.x * fraction.y),
+        ] {
+            for (result, value) in result.iter_mut().zip(self.pixel(x, y)) {
+                *result += value * weight;
+            }
+        }
+        result
+    }
+}
+
+/// Load the sky `name` from `SKY_DIR`, either six Quake style `<name>_rt.png`, ... faces
+/// or one equirectangular `<name>.png`, and build a cubemap for it
+pub async fn load_sky<'a>(name: &str, load_context: &LoadContext<'a>) -> Result<Image, SkyError> {
+    let mut faces = vec![];
+    for (suffix, _) in SKY_FACES {
+        let path = format!("{SKY_DIR}/{name}_{suffix}.png");
+        match read_sky_image(&path, load_context).await? {
+            Some(face) => faces.push(face),
+            None if faces.is_empty() => break,
+            None => return Err(SkyError::MissingFace { path }),
+        }
+    }
+    if !faces.is_empty() {
+        return cubemap_from_faces(&faces);
+    }
+
+    let path = format!("{SKY_DIR}/{name}.png");
+    match read_sky_image(&path, load_context).await? {
+        Some(image) => cubemap_from_equirectangular(&image),
+        None => Err(SkyError::NotFound {
+            name: name.to_string(),
+        }),
+    }
+}
+
+/// `None` if there's no such file
+async fn read_sky_image<'a>(
+    path: &str,
+    load_context: &LoadContext<'a>,
+) -> Result<Option<SkyImage>, SkyError> {
+    let image = read_png(path, true, load_context)
+        .await
+        .map_err(|message| SkyError::Unreadable {
+            path: path.to_string(),
+            message,
+        })?;
+    image
+        .map(|image| SkyImage::from_image(path.to_string(), &image))
+        .transpose()
+}
+
+/// Cubemap from the six faces in `SKY_FACES` order
+fn cubemap_from_faces(faces: &[SkyImage]) -> Result<Image, SkyError> {
+    let expected = UVec2::splat(faces[0].size.x);
+    if let Some(face) = faces.iter().find(|face| face.size != expected) {
+        return Err(SkyError::FaceSize {
+            path: face.path.clone(),
+            size: face.size,
+            expected,
+        });
+    }
+    Ok(build_cubemap(expected.x, |direction| {
+        let (face, uv) = sky_face_uv(direction);
+        faces[face].sample_nearest(uv)
+    }))
+}
+
+/// Cubemap from a panorama that has the map's +X axis in the middle and the horizon halfway down
+fn cubemap_from_equirectangular(image: &SkyImage) -> Result<Image, SkyError> {
+    // Half the height is the cubemap size, which can't be 0
+    if image.size.x != image.size.y * 2 || image.size.y < 2 {
+        return Err(SkyError::EquirectangularSize {
+            path: image.path.clone(),
+            size: image.size,
+        });
+    }
+    Ok(build_cubemap(image.size.y / 2, |direction| {
+        let longitude = direction.y.atan2(direction.x);
+        let latitude = direction.z.clamp(-1.0, 1.0).asin();
+        image.sample_bilinear(Vec2::new(0.5 - longitude / (2.0 * PI), 0.5 - latitude / PI))
+    }))
+}
+
+/// Face index into `SKY_FACES` and UV coordinates on it for a map direction
+fn sky_face_uv(direction: Vec3) -> (usize, Vec2) {
+    let axis = |axes: &[i8; 3], index: i8| {
+        let position = axes
+            .iter()
+            .position(|axis| axis.abs() == index)
+            .expect("Every face has all axes");
+        direction[position] * axes[position].signum() as f32
+    };
+    let (face, distance) = SKY_FACES
+        .iter()
+        .map(|(_, axes)| axis(axes, 3))
+        .enumerate()
+        .fold((0, f32::MIN), |best, (face, distance)| {
+            if distance > best.1 {
+                (face, distance)
+            } else {
+                best
+            }
+        });
+    let axes = &SKY_FACES[face].1;
+    let s = axis(axes, 1) / distance;
+    let t = axis(axes, 2) / distance;
+    (face, Vec2::new((s + 1.0) * 0.5, 1.0 - (t + 1.0) * 0.5))
+}
+
+/// Render every cubemap texel with the color of its map direction.
+/// The sky keeps to Quake's axes whatever the map's up axis, like entity angles.
+fn build_cubemap(size: u32, sample: impl Fn(Vec3) -> [f32; 4]) -> Image {
+    let quake = MapConversion::default();
+    let mut data = Vec::with_capacity((size * size * 6 * 4) as usize);
+    for layer in 0..6 {
+        for y in 0..size {
+            for x in 0..size {
+                let uv = (Vec2::new(x as f32, y as f32) + 0.5) / size as f32;
+                let direction = cube_direction(layer, uv * 2.0 - 1.0);
+                let color = sample(quake.to_map_direction(direction).normalize());
+                data.extend(color.map(|channel| channel.round().clamp(0.0, 255.0) as u8));
+            }
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: size,
+            height: size * 6,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    image.reinterpret_stacked_2d_as_array(6);
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::Cube),
+        ..default()
+    });
+    image
+}
+
+/// World direction of a point on a cubemap layer (+X, -X, +Y, -Y, +Z, -Z),
+/// `st` goes from -1 to 1 with Y down
+fn cube_direction(layer: u32, st: Vec2) -> Vec3 {
+    let Vec2 { x: s, y: t } = st;
+    match layer {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    }
+}
+
+/// Gives sky surfaces the sky of their map. Without one they stay invisible.
+pub fn sky_material_spawner(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<SkyMaterial>>,
+    mut cache: Local<HashMap<Handle<Image>, Handle<SkyMaterial>>>,
+    surface_query: Query<Entity, Added<SkySurface>>,
+    parent_query: Query<&Parent>,
+    sky_query: Query<&MapSky>,
+) {
+    // Let go of the skies of despawned maps so their assets can be freed
+    if !cache.is_empty() {
+        cache.retain(|cubemap, _| sky_query.iter().any(|sky| sky.cubemap == *cubemap));
+    }
+
+    for entity in surface_query.iter() {
+        let sky = match map_component(entity, &parent_query, &sky_query) {
+            Some(sky) => sky,
+            None => continue,
+        };
+        let material = cache
+            .entry(sky.cubemap.clone())
+            .or_insert_with(|| {
+                materials.add(SkyMaterial {
+                    cubemap: sky.cubemap.clone(),
+                })
+            })
+            .clone();
+        commands.entity(entity).insert(material);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::{
+        asset::{AssetPlugin, LoadState},
+        prelude::*,
+        render::render_resource::TextureViewDimension,
+        scene::ScenePlugin,
+    };
+
+    use super::{
+        cubemap_from_equirectangular, cubemap_from_faces, sky_material_spawner, MapSky, SkyImage,
+        SkyMaterial,
+    };
+    use crate::qmap::{component::SkySurface, error::SkyError, QMapPlugin};
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn solid(size: UVec2, color: [u8; 4]) -> SkyImage {
+        SkyImage {
+            path: "sky.png".to_string(),
+            size,
+            data: color.repeat((size.x * size.y) as usize),
+        }
+    }
+
+    /// Color at the center of each cubemap layer
+    fn layer_centers(image: &Image) -> Vec<[u8; 4]> {
+        let size = image.texture_descriptor.size.width as usize;
+        (0..6)
+            .map(|layer| {
+                let index = ((layer * size + size / 2) * size + size / 2) * 4;
+                image.data[index..index + 4].try_into().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn equirectangular_sky() {
+        // Upper half red, lower half blue
+        let mut image = solid(UVec2::new(16, 8), RED);
+        image.data[16 * 4 * 4..].copy_from_slice(&BLUE.repeat(16 * 4));
+        let cubemap = cubemap_from_equirectangular(&image).unwrap();
+        assert_eq!(6, cubemap.texture_descriptor.size.depth_or_array_layers);
+        assert_eq!(4, cubemap.texture_descriptor.size.width);
+        // +Y is up in the world
+        assert_eq!(RED, layer_centers(&cubemap)[2]);
+        assert_eq!(BLUE, layer_centers(&cubemap)[3]);
+
+        assert_eq!(
+            Err(SkyError::EquirectangularSize {
+                path: "sky.png".to_string(),
+                size: UVec2::new(8, 8),
+            }),
+            cubemap_from_equirectangular(&solid(UVec2::splat(8), RED)).map(|_| ())
+        );
+        assert_eq!(
+            Err(SkyError::EquirectangularSize {
+                path: "sky.png".to_string(),
+                size: UVec2::new(2, 1),
+            }),
+            cubemap_from_equirectangular(&solid(UVec2::new(2, 1), RED)).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn face_sizes() {
+        let mut faces: Vec<SkyImage> = (0..6).map(|_| solid(UVec2::splat(4), RED)).collect();
+        faces[4] = solid(UVec2::new(4, 2), RED);
+        assert_eq!(
+            Err(SkyError::FaceSize {
+                path: "sky.png".to_string(),
+                size: UVec2::new(4, 2),
+                expected: UVec2::splat(4),
+            }),
+            cubemap_from_faces(&faces).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn load_sky_cubemap() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_plugin(ScenePlugin)
+            .add_asset::<Image>()
+            .add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>()
+            .add_plugin(QMapPlugin);
+
+        let asset_server = app.world.resource::<AssetServer>().clone();
+        let map: Handle<Scene> = asset_server.load("levels/tests/sky.map");
+        let start = Instant::now();
+        while asset_server.get_load_state(&map) == LoadState::Loading {
+            assert!(start.elapsed() < Duration::from_secs(10), "Map didn't load");
+            std::thread::sleep(Duration::from_millis(10));
+            app.update();
+        }
+        assert_eq!(LoadState::Loaded, asset_server.get_load_state(&map));
+
+        let cubemap = asset_server.get_handle("levels/tests/sky.map#sky");
+        let cubemap = app.world.resource::<Assets<Image>>().get(&cubemap).unwrap();
+        assert_eq!(
+            Some(TextureViewDimension::Cube),
+            cubemap.texture_view_descriptor.as_ref().unwrap().dimension
+        );
+        // Quake's rt faces +X, bk +Y and up +Z, the world has Y up and -Z forward
+        let [rt, lf, bk, ft, up, dn] = [
+            [255, 0, 0, 255],
+            [0, 255, 255, 255],
+            [0, 255, 0, 255],
+            [255, 0, 255, 255],
+            [0, 0, 255, 255],
+            [255, 255, 0, 255],
+        ];
+        assert_eq!(vec![rt, lf, up, dn, ft, bk], layer_centers(cubemap));
+    }
+
+    #[test]
+    fn sky_surface_material() {
+        let mut app = App::new();
+        app.add_plugin(AssetPlugin)
+            .add_asset::<Image>()
+            .add_asset::<SkyMaterial>()
+            .add_system(sky_material_spawner);
+
+        let surface = app.world.spawn().insert(SkySurface).id();
+        let other_surface = app.world.spawn().insert(SkySurface).id();
+        let skyless_surface = app.world.spawn().insert(SkySurface).id();
+        let cubemap = app
+            .world
+            .resource_mut::<Assets<Image>>()
+            .add(Image::default());
+        let map = app
+            .world
+            .spawn()
+            .insert(MapSky {
+                cubemap: cubemap.clone(),
+            })
+            .push_children(&[surface, other_surface])
+            .id();
+        app.world.spawn().push_children(&[skyless_surface]);
+        app.update();
+
+        let material = app.world.get::<Handle<SkyMaterial>>(surface).unwrap();
+        assert_eq!(
+            Some(material),
+            app.world.get::<Handle<SkyMaterial>>(other_surface)
+        );
+        let materials = app.world.resource::<Assets<SkyMaterial>>();
+        assert_eq!(1, materials.len());
+        assert_eq!(cubemap, materials.get(material).unwrap().cubemap);
+        assert!(app
+            .world
+            .get::<Handle<SkyMaterial>>(skyless_surface)
+            .is_none());
+
+        // The material is freed with the map
+        despawn_with_children_recursive(&mut app.world, map);
+        for _ in 0..4 {
+            app.update();
+        }
+        assert_eq!(0, app.world.resource::<Assets<SkyMaterial>>().len());
+    }
+}
@@ -0,0 +1,208 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use super::component::{MapBrushEntity, MapPointEntity};
+
+/// Name other entities refer to with `target` and `killtarget`
+#[derive(Default, Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct TargetName(pub String);
+
+/// Entities named by the `target` key, activated when this entity fires
+#[derive(Default, Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct Targets(pub Vec<Entity>);
+
+/// Entities named by the `killtarget` key, removed when this entity fires
+#[derive(Default, Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct KillTargets(pub Vec<Entity>);
+
+/// Map entities by `targetname`. Names are only unique within a map,
+/// so every map root has its own index.
+#[derive(Default)]
+pub struct MapEntityNames {
+    maps: HashMap<Option<Entity>, HashMap<String, Vec<Entity>>>,
+}
+
+impl MapEntityNames {
+    /// Entities of `map` with the `targetname`, several entities can share one
+    pub fn get(&self, map: Option<Entity>, name: &str) -> &[Entity] {
+        self.maps
+            .get(&map)
+            .and_then(|names| names.get(name))
+            .map_or(&[], |entities| entities.as_slice())
+    }
+
+    fn insert(&mut self, map: Option<Entity>, name: &str, entity: Entity) {
+        let entities = self
+            .maps
+            .entry(map)
+            .or_default()
+            .entry(name.to_string())
+            .or_default();
+        if !entities.contains(&entity) {
+            entities.push(entity);
+        }
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        for names in self.maps.values_mut() {
+            for entities in names.values_mut() {
+                entities.retain(|named| *named != entity);
+            }
+            names.retain(|_, entities| !entities.is_empty());
+        }
+        self.maps.retain(|_, names| !names.is_empty());
+    }
+}
+
+/// Resolves `target` and `killtarget` of newly spawned map entities into `Targets` and
+/// `KillTargets`. Every entity of a map is spawned at once, so all names are known here.
+/// Targets that are despawned later, e.g. by a `killtarget`, are dropped.
+#[allow(clippy::too_many_arguments)]
+pub fn target_linker(
+    mut commands: Commands,
+    mut names: ResMut<MapEntityNames>,
+    point_query: Query<(Entity, &MapPointEntity, Option<&Parent>), Added<MapPointEntity>>,
+    brush_query: Query<(Entity, &MapBrushEntity, Option<&Parent>), Added<MapBrushEntity>>,
+    mut targets_query: Query<&mut Targets>,
+    mut kill_targets_query: Query<&mut KillTargets>,
+    entities: Query<Entity>,
+    removed: RemovedComponents<TargetName>,
+) {
+    for entity in removed.iter() {
+        names.remove(entity);
+    }
+
+    // Only targets that lost an entity are touched, so the rest are not marked as changed
+    let is_dead = |target: &Entity| !entities.contains(*target);
+    for mut targets in targets_query.iter_mut() {
+        if targets.0.iter().any(is_dead) {
+            targets.0.retain(|target| !is_dead(target));
+        }
+    }
+    for mut targets in kill_targets_query.iter_mut() {
+        if targets.0.iter().any(is_dead) {
+            targets.0.retain(|target| !is_dead(target));
+        }
+    }
+
+    // Map entities are children of the map root
+    let added: Vec<AddedEntity> = point_query
+        .iter()
+        .map(|(entity, map_entity, parent)| AddedEntity {
+            entity,
+            classname: &map_entity.name,
+            properties: &map_entity.properties,
+            map: parent.map(|parent| parent.get()),
+        })
+        .chain(
+            brush_query
+                .iter()
+                .map(|(entity, map_entity, parent)| AddedEntity {
+                    entity,
+                    classname: &map_entity.name,
+                    properties: &map_entity.properties,
+                    map: parent.map(|parent| parent.get()),
+                }),
+        )
+        .collect();
+
+    for added in added.iter() {
+        if let Some(name) = non_empty(added.properties, "targetname") {
+            names.insert(added.map, name, added.entity);
+            commands
+                .entity(added.entity)
+                .insert(TargetName(name.to_string()));
+        }
+    }
+
+    for added in added.iter() {
+        let resolve = |key: &str| {
+            let name = non_empty(added.properties, key)?;
+            let targets = names.get(added.map, name).to_vec();
+            if targets.is_empty() {
+                warn!(
+                    "Map entity {} has {key} {name}, but no entity has that targetname",
+                    added.classname
+                );
+            }
+            Some(targets)
+        };
+        if let Some(targets) = resolve("target") {
+            commands.entity(added.entity).insert(Targets(targets));
+        }
+        if let Some(targets) = resolve("killtarget") {
+            commands.entity(added.entity).insert(KillTargets(targets));
+        }
+    }
+}
+
+struct AddedEntity<'a> {
+    entity: Entity,
+    classname: &'a str,
+    properties: &'a HashMap<String, String>,
+    map: Option<Entity>,
+}
+
+fn non_empty<'a>(properties: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    properties
+        .get(key)
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{target_linker, KillTargets, MapEntityNames, TargetName, Targets};
+    use crate::qmap::test_util::{brush, point};
+
+    #[test]
+    fn link_targets() {
+        let mut app = App::new();
+        app.init_resource::<MapEntityNames>()
+            .add_system(target_linker);
+
+        let button = brush(
+            &mut app,
+            "func_button",
+            &[("target", "door1"), ("killtarget", "light1")],
+        );
+        let door = brush(&mut app, "func_door", &[("targetname", "door1")]);
+        let other_door = brush(&mut app, "func_door", &[("targetname", "door1")]);
+        let light = point(&mut app, "light_point", &[("targetname", "light1")]);
+        let relay = point(&mut app, "trigger_relay", &[("target", "nothing")]);
+        let map = app.world.spawn().id();
+        app.world
+            .entity_mut(map)
+            .push_children(&[button, door, other_door, light, relay]);
+
+        // A second map with the same names doesn't interfere
+        let second_door = brush(&mut app, "func_door", &[("targetname", "door1")]);
+        let second_map = app.world.spawn().push_children(&[second_door]).id();
+        app.update();
+
+        assert_eq!(
+            vec![door, other_door],
+            app.world.get::<Targets>(button).unwrap().0
+        );
+        assert_eq!(vec![light], app.world.get::<KillTargets>(button).unwrap().0);
+        assert_eq!("door1", app.world.get::<TargetName>(door).unwrap().0);
+        // Dangling targets are reported and left empty
+        assert!(app.world.get::<Targets>(relay).unwrap().0.is_empty());
+        assert!(app.world.get::<Targets>(door).is_none());
+
+        let names = app.world.resource::<MapEntityNames>();
+        assert_eq!(&[second_door], names.get(Some(second_map), "door1"));
+
+        app.world.despawn(other_door);
+        app.world.despawn(light);
+        app.update();
+        let names = app.world.resource::<MapEntityNames>();
+        assert_eq!(&[door], names.get(Some(map), "door1"));
+        assert_eq!(vec![door], app.world.get::<Targets>(button).unwrap().0);
+        assert!(app.world.get::<KillTargets>(button).unwrap().0.is_empty());
+    }
+}
@@ -0,0 +1,33 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use super::component::{MapBrushEntity, MapPointEntity};
+
+pub fn properties(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Spawn a map point entity without a parent
+pub fn point(app: &mut App, name: &str, pairs: &[(&str, &str)]) -> Entity {
+    app.world
+        .spawn()
+        .insert(MapPointEntity {
+            name: name.to_string(),
+            properties: properties(pairs),
+            ..default()
+        })
+        .id()
+}
+
+/// Spawn a map brush entity without a parent
+pub fn brush(app: &mut App, name: &str, pairs: &[(&str, &str)]) -> Entity {
+    app.world
+        .spawn()
+        .insert(MapBrushEntity {
+            name: name.to_string(),
+            properties: properties(pairs),
+        })
+        .id()
+}
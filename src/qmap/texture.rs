@@ -0,0 +1,151 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bevy::{
+    asset::{AssetIoError, LoadContext},
+    prelude::*,
+    render::texture::{CompressedImageFormats, ImageType},
+};
+use shalrath::repr::Map;
+
+use super::{build::ToolTexture, types::DEFAULT_TEXTURE_SIZE, wad::MapWads};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+pub fn texture_image_path(texture: &str) -> String {
+    format!("textures/{texture}.png")
+}
+
+/// Textures referenced by the map's faces, without tool textures
+pub fn map_textures(map: &Map) -> BTreeSet<&str> {
+    map.0
+        .iter()
+        .flat_map(|entity| entity.brushes.iter())
+        .flat_map(|brush| brush.0.iter())
+        .map(|plane| plane.texture.as_str())
+        .filter(|texture| ToolTexture::from_texture(texture).is_none())
+        .collect()
+}
+
+/// Pixel sizes of the textures referenced by the map, with the PNGs they were read from
+#[derive(Default)]
+pub struct MapTextureImages {
+    pub sizes: HashMap<String, Vec2>,
+    /// Decoded from the bytes the sizes came from, so the asset server doesn't read them again
+    pub images: HashMap<String, Image>,
+}
+
+/// Read the pixel size of every texture referenced by the map, from the map's WADs or the PNGs.
+/// Textures that can't be read fall back to `DEFAULT_TEXTURE_SIZE`.
+pub async fn load_texture_images<'a>(
+    map: &Map,
+    wads: &MapWads,
+    load_context: &LoadContext<'a>,
+) -> MapTextureImages {
+    let mut textures = MapTextureImages::default();
+    for texture in map_textures(map) {
+        if let Some(wad_texture) = wads.get(texture) {
+            textures.sizes.insert(texture.to_string(), wad_texture.size);
+            continue;
+        }
+        let path = texture_image_path(texture);
+        let bytes = match load_context.read_asset_bytes(&path).await {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                warn!("Could not read {path}, using default texture size: {err}");
+                textures
+                    .sizes
+                    .insert(texture.to_string(), DEFAULT_TEXTURE_SIZE);
+                continue;
+            }
+        };
+        let size = png_size(&bytes).unwrap_or_else(|| {
+            warn!("Could not read image header of {path}, using default texture size");
+            DEFAULT_TEXTURE_SIZE
+        });
+        textures.sizes.insert(texture.to_string(), size);
+        match decode_png(&bytes, true) {
+            Ok(image) => {
+                textures.images.insert(texture.to_string(), image);
+            }
+            Err(err) => warn!("Could not decode {path}: {err}"),
+        }
+    }
+    textures
+}
+
+/// Decode a PNG from the assets folder, `Ok(None)` if there's no such file.
+/// Data like normal maps isn't color and must not be read as sRGB.
+pub async fn read_png<'a>(
+    path: &str,
+    is_srgb: bool,
+    load_context: &LoadContext<'a>,
+) -> Result<Option<Image>, String> {
+    let bytes = match load_context.read_asset_bytes(path).await {
+        Ok(bytes) => bytes,
+        Err(AssetIoError::NotFound(_)) => return Ok(None),
+        Err(err) => return Err(err.to_string()),
+    };
+    decode_png(&bytes, is_srgb).map(Some)
+}
+
+fn decode_png(bytes: &[u8], is_srgb: bool) -> Result<Image, String> {
+    Image::from_buffer(
+        bytes,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        is_srgb,
+    )
+    .map_err(|err| err.to_string())
+}
+
+/// Read the image dimensions from the IHDR chunk of a PNG without decoding the image
+pub fn png_size(bytes: &[u8]) -> Option<Vec2> {
+    if bytes.len() < 24 || bytes[0..8] != PNG_SIGNATURE || &bytes[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(bytes[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(bytes[20..24].try_into().ok()?);
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some(Vec2 {
+        x: width as f32,
+        y: height as f32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{decode_png, png_size};
+
+    #[test]
+    fn png_header() {
+        assert_eq!(
+            Some(Vec2::new(32.0, 32.0)),
+            png_size(include_bytes!("../../assets/textures/station/wall_1.png"))
+        );
+
+        let mut header = super::PNG_SIGNATURE.to_vec();
+        header.extend_from_slice(&13_u32.to_be_bytes());
+        header.extend_from_slice(b"IHDR");
+        header.extend_from_slice(&64_u32.to_be_bytes());
+        header.extend_from_slice(&128_u32.to_be_bytes());
+        assert_eq!(Some(Vec2::new(64.0, 128.0)), png_size(&header));
+    }
+
+    #[test]
+    fn decoded_png() {
+        let bytes = include_bytes!("../../assets/textures/station/wall_1.png");
+        let image = decode_png(bytes, true).unwrap();
+        assert_eq!(png_size(bytes), Some(image.size()));
+        assert!(decode_png(b"not a png file at all...", true).is_err());
+    }
+
+    #[test]
+    fn invalid_png_header() {
+        assert_eq!(None, png_size(&[]));
+        assert_eq!(None, png_size(b"not a png file at all..."));
+    }
+}
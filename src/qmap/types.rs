@@ -1,8 +1,8 @@
 pub use bevy::prelude::*;
 use shalrath::repr::TrianglePlane;
 
-pub const INVERSE_SCALE_FACTOR: f32 = 16.0;
-pub const MAP_SCALE: f32 = 1.0 / INVERSE_SCALE_FACTOR;
+/// Used for UV generation when the texture image can't be read
+pub const DEFAULT_TEXTURE_SIZE: Vec2 = Vec2 { x: 32.0, y: 32.0 };
 
 #[derive(Clone, Copy, Debug)]
 pub struct Vertex {
@@ -43,6 +43,9 @@ impl From<TrianglePlane> for Plane {
     }
 }
 
+/// Vertex positions, uvs and normals
+pub type FaceAttributes = (Vec<[f32; 3]>, Vec<[f32; 2]>, Vec<[f32; 3]>);
+
 #[derive(Clone)]
 pub struct Face {
     pub plane: Plane,
@@ -51,9 +54,8 @@ pub struct Face {
 }
 
 impl Face {
-    pub fn as_tuples(&self) -> (Vec<[f32; 3]>, Vec<[f32; 2]>, Vec<[f32; 3]>) {
-        let mut tuples: (Vec<[f32; 3]>, Vec<[f32; 2]>, Vec<[f32; 3]>) =
-            (Vec::new(), Vec::new(), Vec::new());
+    pub fn as_tuples(&self) -> FaceAttributes {
+        let mut tuples: FaceAttributes = (Vec::new(), Vec::new(), Vec::new());
         for vert in self.vertices.iter() {
             tuples.0.push(vec3_to_arr(vert.position));
             tuples.1.push(vec2_to_arr(vert.uv));
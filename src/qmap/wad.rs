@@ -0,0 +1,572 @@
+use std::{collections::HashMap, path::Path};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    utils::BoxedFuture,
+};
+
+use super::error::WadError;
+
+/// Worldspawn key listing the WADs to take face textures from, separated by `;`
+pub const WAD_KEY: &str = "wad";
+/// The Quake palette, for WAD2 files without a `palette` lump
+pub const QUAKE_PALETTE_PATH: &str = "gfx/palette.lmp";
+
+const HEADER_SIZE: usize = 12;
+const DIRECTORY_ENTRY_SIZE: usize = 32;
+const NAME_SIZE: usize = 16;
+const MIPTEX_HEADER_SIZE: usize = 40;
+const PALETTE_SIZE: usize = 256 * 3;
+const LUMP_PALETTE: u8 = 0x40;
+const LUMP_MIPTEX_WAD3: u8 = 0x43;
+const LUMP_MIPTEX_WAD2: u8 = 0x44;
+/// Palette index drawn transparent in `{` textures
+const TRANSPARENT_INDEX: u8 = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WadVersion {
+    /// Quake, textures use the Quake palette
+    Wad2,
+    /// Half-Life, every texture has its own palette
+    Wad3,
+}
+
+/// Directory entry of a WAD
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WadLump {
+    /// Lowercase, map editors and compilers ignore the case of texture names
+    pub name: String,
+    pub kind: u8,
+    offset: usize,
+    size: usize,
+    compression: u8,
+}
+
+/// A parsed WAD directory over the file's bytes
+pub struct WadFile<'a> {
+    pub version: WadVersion,
+    pub lumps: Vec<WadLump>,
+    bytes: &'a [u8],
+}
+
+impl<'a> WadFile<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, WadError> {
+        let version = match bytes.get(0..4) {
+            Some(b"WAD2") => WadVersion::Wad2,
+            Some(b"WAD3") => WadVersion::Wad3,
+            _ => return Err(WadError::InvalidHeader),
+        };
+        let count = read_u32(bytes, 4).ok_or(WadError::InvalidHeader)? as usize;
+        let directory = read_u32(bytes, 8).ok_or(WadError::InvalidHeader)? as usize;
+        if directory < HEADER_SIZE
+            || count
+                .checked_mul(DIRECTORY_ENTRY_SIZE)
+                .and_then(|size| size.checked_add(directory))
+                .map_or(true, |end| end > bytes.len())
+        {
+            return Err(WadError::InvalidHeader);
+        }
+
+        let lumps = bytes[directory..directory + count * DIRECTORY_ENTRY_SIZE]
+            .chunks_exact(DIRECTORY_ENTRY_SIZE)
+            .map(|entry| WadLump {
+                name: read_name(&entry[16..16 + NAME_SIZE]),
+                kind: entry[12],
+                offset: read_u32(entry, 0).unwrap_or_default() as usize,
+                size: read_u32(entry, 4).unwrap_or_default() as usize,
+                compression: entry[13],
+            })
+            .collect();
+        Ok(WadFile {
+            version,
+            lumps,
+            bytes,
+        })
+    }
+
+    /// The miptex lumps
+    pub fn textures(&self) -> impl Iterator<Item = &WadLump> {
+        let kind = match self.version {
+            WadVersion::Wad2 => LUMP_MIPTEX_WAD2,
+            WadVersion::Wad3 => LUMP_MIPTEX_WAD3,
+        };
+        self.lumps.iter().filter(move |lump| lump.kind == kind)
+    }
+
+    /// Pixel size of a texture, read from its header without decoding it
+    pub fn texture_size(&self, lump: &WadLump) -> Result<UVec2, WadError> {
+        let bytes = self.lump_bytes(lump)?;
+        miptex_size(bytes).ok_or_else(|| WadError::InvalidLump {
+            name: lump.name.clone(),
+        })
+    }
+
+    /// The palette lump some WAD2 files carry, like Quake's `gfx.wad`
+    pub fn palette(&self) -> Option<&'a [u8]> {
+        self.lumps
+            .iter()
+            .filter(|lump| lump.kind == LUMP_PALETTE)
+            .find_map(|lump| self.lump_bytes(lump).ok())
+            .filter(|bytes| bytes.len() >= PALETTE_SIZE)
+    }
+
+    /// Decode the full size mip level of a texture. WAD2 textures need the Quake `palette`,
+    /// WAD3 textures bring their own. Index 255 is transparent in textures starting with `{`.
+    pub fn decode(&self, lump: &WadLump, palette: Option<&[u8]>) -> Result<Image, WadError> {
+        let invalid = || WadError::InvalidLump {
+            name: lump.name.clone(),
+        };
+        let bytes = self.lump_bytes(lump)?;
+        let size = miptex_size(bytes).ok_or_else(invalid)?;
+        let pixel_count = size
+            .x
+            .checked_mul(size.y)
+            .filter(|count| *count > 0)
+            .ok_or_else(invalid)? as usize;
+        let offset = read_u32(bytes, 24).ok_or_else(invalid)? as usize;
+        let pixels = offset
+            .checked_add(pixel_count)
+            .filter(|_| offset >= MIPTEX_HEADER_SIZE)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(invalid)?;
+
+        let palette = match self.version {
+            WadVersion::Wad2 => palette.ok_or(WadError::MissingPalette)?,
+            WadVersion::Wad3 => {
+                // The palette follows the smallest mip level
+                let last_mip = read_u32(bytes, 36).ok_or_else(invalid)? as usize;
+                let start = last_mip + ((size.x / 8) * (size.y / 8)) as usize;
+                let colors = bytes
+                    .get(start..start + 2)
+                    .map(|count| u16::from_le_bytes([count[0], count[1]]) as usize)
+                    .ok_or_else(invalid)?;
+                bytes
+                    .get(start + 2..start + 2 + colors.min(256) * 3)
+                    .ok_or_else(invalid)?
+            }
+        };
+
+        let transparent = lump.name.starts_with('{');
+        let data = pixels
+            .iter()
+            .flat_map(|&index| {
+                if transparent && index == TRANSPARENT_INDEX {
+                    return [0, 0, 0, 0];
+                }
+                let color = index as usize * 3;
+                match palette.get(color..color + 3) {
+                    Some(rgb) => [rgb[0], rgb[1], rgb[2], 255],
+                    None => [0, 0, 0, 255],
+                }
+            })
+            .collect();
+        Ok(Image::new(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        ))
+    }
+
+    fn lump_bytes(&self, lump: &WadLump) -> Result<&'a [u8], WadError> {
+        if lump.compression != 0 {
+            return Err(WadError::Compressed {
+                name: lump.name.clone(),
+            });
+        }
+        lump.offset
+            .checked_add(lump.size)
+            .and_then(|end| self.bytes.get(lump.offset..end))
+            .ok_or_else(|| WadError::InvalidLump {
+                name: lump.name.clone(),
+            })
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Null terminated, the rest of the field may hold garbage
+fn read_name(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_lowercase()
+}
+
+fn miptex_size(bytes: &[u8]) -> Option<UVec2> {
+    Some(UVec2::new(read_u32(bytes, 16)?, read_u32(bytes, 20)?))
+}
+
+/// Textures of a WAD. Each one is an image labeled with its lowercase name,
+/// like `textures/base.wad#{grate`.
+#[derive(Debug, TypeUuid)]
+#[uuid = "0c9a5f6e-3b1d-4a27-9e58-8d2f71c4b6a3"]
+pub struct Wad {
+    pub textures: HashMap<String, Handle<Image>>,
+}
+
+/// Loads WAD2 and WAD3 files, textures that can't be decoded are skipped
+#[derive(Default)]
+pub struct WadLoader;
+
+impl AssetLoader for WadLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let wad = WadFile::parse(bytes)?;
+            let quake_palette = match (wad.version, wad.palette()) {
+                (WadVersion::Wad2, None) => {
+                    match load_context.read_asset_bytes(QUAKE_PALETTE_PATH).await {
+                        Ok(palette) => Some(palette),
+                        Err(err) => {
+                            warn!("Could not read {QUAKE_PALETTE_PATH}: {err}");
+                            None
+                        }
+                    }
+                }
+                (_, palette) => palette.map(<[u8]>::to_vec),
+            };
+
+            let mut textures = HashMap::new();
+            for lump in wad.textures() {
+                match wad.decode(lump, quake_palette.as_deref()) {
+                    Ok(image) => {
+                        let handle =
+                            load_context.set_labeled_asset(&lump.name, LoadedAsset::new(image));
+                        textures.insert(lump.name.clone(), handle);
+                    }
+                    Err(err) => warn!(
+                        "Could not decode {} in {:?}: {err}",
+                        lump.name,
+                        load_context.path()
+                    ),
+                }
+            }
+            load_context.set_default_asset(LoadedAsset::new(Wad { textures }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["wad"]
+    }
+}
+
+/// A face texture found in one of the map's WADs
+#[derive(Clone, Debug, PartialEq)]
+pub struct WadTexture {
+    /// Asset path of the WAD
+    pub wad: String,
+    pub size: Vec2,
+}
+
+impl WadTexture {
+    /// Path of the labeled image `WadLoader` decodes the texture into
+    pub fn image_path(&self, texture: &str) -> String {
+        format!("{}#{}", self.wad, texture.to_lowercase())
+    }
+}
+
+/// Textures of the WADs listed in the worldspawn `wad` key, read before building the map.
+/// Face textures are looked up here before their PNGs, the first WAD with a texture wins.
+#[derive(Clone, Debug, Default)]
+pub struct MapWads {
+    textures: HashMap<String, WadTexture>,
+}
+
+impl MapWads {
+    pub async fn load<'a>(
+        worldspawn: &bevy::utils::HashMap<String, String>,
+        load_context: &LoadContext<'a>,
+    ) -> Self {
+        let mut wads = MapWads::default();
+        let entries = match worldspawn.get(WAD_KEY) {
+            Some(entries) => entries,
+            None => return wads,
+        };
+        let map_directory = load_context.path().parent().unwrap_or(Path::new(""));
+        for entry in entries
+            .split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let path = match wad_candidates(entry, map_directory)
+                .into_iter()
+                .find(|path| load_context.asset_io().is_file(Path::new(path)))
+            {
+                Some(path) => path,
+                None => {
+                    warn!("WAD {entry} of {:?} not found", load_context.path());
+                    continue;
+                }
+            };
+            let bytes = match load_context.read_asset_bytes(&path).await {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    warn!("Could not read {path}: {err}");
+                    continue;
+                }
+            };
+            match WadFile::parse(&bytes) {
+                Ok(wad) => wads.add(&path, &wad),
+                Err(err) => warn!("Could not read {path}: {err}"),
+            }
+        }
+        wads
+    }
+
+    fn add(&mut self, path: &str, wad: &WadFile) {
+        for lump in wad.textures() {
+            match wad.texture_size(lump) {
+                Ok(size) => {
+                    self.textures
+                        .entry(lump.name.clone())
+                        .or_insert_with(|| WadTexture {
+                            wad: path.to_string(),
+                            size: size.as_vec2(),
+                        });
+                }
+                Err(err) => warn!("Could not read {} in {path}: {err}", lump.name),
+            }
+        }
+    }
+
+    pub fn get(&self, texture: &str) -> Option<&WadTexture> {
+        self.textures.get(&texture.to_lowercase())
+    }
+
+    /// Asset path of the texture's image if a WAD has it
+    pub fn image_path(&self, texture: &str) -> Option<String> {
+        self.get(texture)
+            .map(|wad_texture| wad_texture.image_path(texture))
+    }
+}
+
+/// Asset paths to try for an entry of the `wad` key: relative to the assets folder, then to the
+/// map. TrenchBroom may write absolute paths, those are looked up by file name next to the map
+/// and in `textures/`.
+pub fn wad_candidates(entry: &str, map_directory: &Path) -> Vec<String> {
+    let entry = entry.replace('\\', "/");
+    let is_absolute = entry.starts_with('/') || entry.get(1..2) == Some(":");
+    let file_name = entry.rsplit('/').next().unwrap_or(&entry).to_string();
+    let map_directory = map_directory.to_string_lossy().replace('\\', "/");
+    let in_map_directory = |path: &str| match map_directory.is_empty() {
+        true => path.to_string(),
+        false => format!("{map_directory}/{path}"),
+    };
+
+    let mut candidates = match is_absolute {
+        true => vec![in_map_directory(&file_name)],
+        false => vec![entry.clone(), in_map_directory(&entry)],
+    };
+    candidates.push(format!("textures/{file_name}"));
+    let mut unique = vec![];
+    for candidate in candidates {
+        if !unique.contains(&candidate) {
+            unique.push(candidate);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use bevy::prelude::*;
+
+    use crate::qmap::error::WadError;
+
+    use super::{
+        wad_candidates, MapWads, WadFile, WadVersion, LUMP_MIPTEX_WAD2, LUMP_MIPTEX_WAD3,
+        LUMP_PALETTE,
+    };
+
+    /// A 16x8 miptex whose pixels are their index modulo 4
+    fn miptex(name: &str, palette: Option<&[u8]>) -> Vec<u8> {
+        sized_miptex(name, UVec2::new(16, 8), palette)
+    }
+
+    fn sized_miptex(name: &str, size: UVec2, palette: Option<&[u8]>) -> Vec<u8> {
+        let (width, height) = (size.x, size.y);
+        let mut bytes = vec![0; 16];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        let mut offset = 40u32;
+        let mut mips = vec![];
+        for level in 0..4 {
+            bytes.extend(offset.to_le_bytes());
+            let count = (width >> level) * (height >> level);
+            mips.extend((0..count).map(|index| (index % 4) as u8));
+            offset += count;
+        }
+        bytes.extend(mips);
+        if let Some(palette) = palette {
+            bytes.extend(((palette.len() / 3) as u16).to_le_bytes());
+            bytes.extend(palette);
+        }
+        bytes
+    }
+
+    fn wad_bytes(magic: &[u8; 4], lumps: &[(&str, u8, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        bytes.extend((lumps.len() as u32).to_le_bytes());
+        let data_size: usize = lumps.iter().map(|(_, _, data)| data.len()).sum();
+        bytes.extend(((12 + data_size) as u32).to_le_bytes());
+        let mut directory = vec![];
+        for (name, kind, data) in lumps {
+            directory.extend((bytes.len() as u32).to_le_bytes());
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend([*kind, 0, 0, 0]);
+            let mut name_field = [0; 16];
+            name_field[..name.len()].copy_from_slice(name.as_bytes());
+            directory.extend(name_field);
+            bytes.extend(data);
+        }
+        bytes.extend(directory);
+        bytes
+    }
+
+    fn palette() -> Vec<u8> {
+        let mut palette: Vec<u8> = (0..=255u8).flat_map(|index| [index, 0, 0]).collect();
+        palette[3..6].copy_from_slice(&[0, 255, 0]);
+        palette
+    }
+
+    #[test]
+    fn decode_wad3() {
+        let bytes = wad_bytes(
+            b"WAD3",
+            &[
+                ("WALL1", LUMP_MIPTEX_WAD3, miptex("WALL1", Some(&palette()))),
+                ("{GRATE", LUMP_MIPTEX_WAD3, {
+                    let mut texture = miptex("{GRATE", Some(&palette()));
+                    texture[40] = 255;
+                    texture
+                }),
+            ],
+        );
+        let wad = WadFile::parse(&bytes).unwrap();
+        assert_eq!(WadVersion::Wad3, wad.version);
+        let names: Vec<&str> = wad.textures().map(|lump| lump.name.as_str()).collect();
+        assert_eq!(vec!["wall1", "{grate"], names);
+
+        let wall = wad.decode(&wad.lumps[0], None).unwrap();
+        assert_eq!(UVec2::new(16, 8), wall.size().as_uvec2());
+        assert_eq!(UVec2::new(16, 8), wad.texture_size(&wad.lumps[0]).unwrap());
+        assert_eq!(&[0, 0, 0, 255, 0, 255, 0, 255], &wall.data[..8]);
+
+        // Only `{` textures have a transparent index
+        let grate = wad.decode(&wad.lumps[1], None).unwrap();
+        assert_eq!(&[0, 0, 0, 0], &grate.data[..4]);
+    }
+
+    #[test]
+    fn decode_wad3_odd_size() {
+        // The smallest mip level is 2x1, the palette follows its 2 pixels
+        let size = UVec2::new(20, 12);
+        let texture = sized_miptex("pipe", size, Some(&palette()));
+        let bytes = wad_bytes(b"WAD3", &[("pipe", LUMP_MIPTEX_WAD3, texture)]);
+        let wad = WadFile::parse(&bytes).unwrap();
+        let pipe = wad.decode(&wad.lumps[0], None).unwrap();
+        assert_eq!(size, pipe.size().as_uvec2());
+        assert_eq!(&[0, 0, 0, 255, 0, 255, 0, 255], &pipe.data[..8]);
+    }
+
+    #[test]
+    fn decode_wad2() {
+        let texture = ("floor", LUMP_MIPTEX_WAD2, miptex("floor", None));
+        let bytes = wad_bytes(b"WAD2", std::slice::from_ref(&texture));
+        let wad = WadFile::parse(&bytes).unwrap();
+        assert_eq!(None, wad.palette());
+        assert_eq!(
+            Err(WadError::MissingPalette),
+            wad.decode(&wad.lumps[0], None).map(|_| ())
+        );
+        let image = wad.decode(&wad.lumps[0], Some(&palette())).unwrap();
+        assert_eq!(&[0, 255, 0, 255], &image.data[4..8]);
+
+        let bytes = wad_bytes(b"WAD2", &[("palette", LUMP_PALETTE, palette()), texture]);
+        let wad = WadFile::parse(&bytes).unwrap();
+        assert_eq!(Some(palette().as_slice()), wad.palette());
+
+        let mut map_wads = MapWads::default();
+        map_wads.add("textures/base.wad", &wad);
+        assert_eq!(
+            Some("textures/base.wad#floor".to_string()),
+            map_wads.image_path("FLOOR")
+        );
+        assert_eq!(Vec2::new(16.0, 8.0), map_wads.get("floor").unwrap().size);
+        assert_eq!(None, map_wads.get("palette"));
+    }
+
+    #[test]
+    fn invalid_wad() {
+        assert_eq!(Some(WadError::InvalidHeader), WadFile::parse(b"PACK").err());
+        let mut bytes = wad_bytes(b"WAD3", &[("wall", LUMP_MIPTEX_WAD3, miptex("wall", None))]);
+        assert_eq!(Some(WadError::InvalidHeader), {
+            let mut truncated = bytes.clone();
+            truncated.truncate(bytes.len() - 1);
+            WadFile::parse(&truncated).err()
+        });
+
+        // The WAD3 texture has no palette
+        let wad = WadFile::parse(&bytes).unwrap();
+        assert_eq!(
+            Err(WadError::InvalidLump {
+                name: "wall".to_string()
+            }),
+            wad.decode(&wad.lumps[0], None).map(|_| ())
+        );
+        let compression = bytes.len() - 32 + 13;
+        bytes[compression] = 1;
+        let wad = WadFile::parse(&bytes).unwrap();
+        assert_eq!(
+            Err(WadError::Compressed {
+                name: "wall".to_string()
+            }),
+            wad.texture_size(&wad.lumps[0])
+        );
+    }
+
+    #[test]
+    fn wad_paths() {
+        let map_directory = Path::new("levels");
+        assert_eq!(
+            vec![
+                "wads/base.wad".to_string(),
+                "levels/wads/base.wad".to_string(),
+                "textures/base.wad".to_string()
+            ],
+            wad_candidates("wads\\base.wad", map_directory)
+        );
+        assert_eq!(
+            vec![
+                "levels/halflife.wad".to_string(),
+                "textures/halflife.wad".to_string()
+            ],
+            wad_candidates("C:\\Games\\valve\\halflife.wad", map_directory)
+        );
+        assert_eq!(
+            vec!["textures/base.wad".to_string()],
+            wad_candidates("/quake/id1/textures/base.wad", Path::new("textures"))
+        );
+    }
+}
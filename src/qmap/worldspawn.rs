@@ -0,0 +1,218 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier3d::prelude::*;
+
+use super::properties::{FromMapProperties, PropertyError};
+
+/// Environment of the last loaded map, read from its worldspawn keys
+#[derive(FromMapProperties, Clone, Debug, PartialEq)]
+#[map(solid_class = "worldspawn", description = "World entity")]
+pub struct Worldspawn {
+    #[map(
+        rename = "_ambient",
+        default = 1.0,
+        display = "Ambient light",
+        description = "Brightness of the ambient light"
+    )]
+    pub ambient: f32,
+    #[map(
+        rename = "_ambient_color",
+        default = "Color::WHITE",
+        display = "Ambient color"
+    )]
+    pub ambient_color: Color,
+    #[map(
+        rename = "_clear_color",
+        display = "Background color",
+        description = "Shown where nothing is drawn"
+    )]
+    pub clear_color: Option<Color>,
+    #[map(
+        default = 9.81,
+        description = "Downward acceleration in world units per second squared, not scaled by _scale, 0 for zero-g"
+    )]
+    pub gravity: f32,
+    #[map(description = "Level title")]
+    pub message: Option<String>,
+    #[map(
+        description = "Skybox in textures/sky, six <sky>_rt.png, ... faces or an equirectangular <sky>.png"
+    )]
+    pub sky: Option<String>,
+    #[map(
+        display = "WAD files",
+        description = "WAD2 or WAD3 files to take face textures from before the PNGs, separated by ;"
+    )]
+    pub wad: Option<String>,
+    #[map(
+        rename = "_scale",
+        display = "Scale",
+        description = "World units per map unit, 0.0625 by default"
+    )]
+    pub scale: Option<f32>,
+    #[map(
+        rename = "_up_axis",
+        display = "Up axis",
+        description = "z like Quake (default) or y"
+    )]
+    pub up_axis: Option<String>,
+}
+
+impl Default for Worldspawn {
+    fn default() -> Self {
+        Worldspawn::from_map_properties(&HashMap::new()).expect("Every key has a default")
+    }
+}
+
+impl Worldspawn {
+    /// Like `from_map_properties`, but invalid keys are reported and replaced by their default
+    pub fn from_properties_or_default(properties: &HashMap<String, String>) -> Self {
+        let errors = match Worldspawn::from_map_properties(properties)
+            .and_then(|worldspawn| worldspawn.validate(properties))
+        {
+            Ok(worldspawn) => return worldspawn,
+            Err(errors) => errors,
+        };
+        let mut properties = properties.clone();
+        for err in errors {
+            warn!("Worldspawn: {err}");
+            if let PropertyError::Invalid { key, .. } = err {
+                properties.remove(&key);
+            }
+        }
+        Worldspawn::from_map_properties(&properties).unwrap_or_default()
+    }
+
+    /// Gravity goes to Rapier as it is, in world units rather than map units like lengths
+    fn validate(self, properties: &HashMap<String, String>) -> Result<Self, Vec<PropertyError>> {
+        if self.gravity.is_finite() && self.gravity >= 0.0 {
+            return Ok(self);
+        }
+        Err(vec![PropertyError::Invalid {
+            key: "gravity".to_string(),
+            value: properties.get("gravity").cloned().unwrap_or_default(),
+            expected: "0 or more world units per second squared",
+        }])
+    }
+}
+
+/// Worldspawn keys of a map, on the map root
+#[derive(Default, Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct MapWorldspawn {
+    pub properties: HashMap<String, String>,
+}
+
+/// Makes the worldspawn of a newly spawned map the current `Worldspawn`
+pub fn worldspawn_loader(
+    mut commands: Commands,
+    query: Query<&MapWorldspawn, Added<MapWorldspawn>>,
+) {
+    for map_worldspawn in query.iter() {
+        commands.insert_resource(Worldspawn::from_properties_or_default(
+            &map_worldspawn.properties,
+        ));
+    }
+}
+
+/// Applies the ambient light, clear color, gravity and title of the current `Worldspawn`
+pub fn apply_worldspawn(
+    mut commands: Commands,
+    worldspawn: Option<Res<Worldspawn>>,
+    rapier_config: Option<ResMut<RapierConfiguration>>,
+    windows: Option<ResMut<Windows>>,
+) {
+    let worldspawn = match worldspawn {
+        Some(worldspawn) if worldspawn.is_changed() => worldspawn,
+        _ => return,
+    };
+    commands.insert_resource(AmbientLight {
+        color: worldspawn.ambient_color,
+        brightness: worldspawn.ambient,
+    });
+    // Maps without a clear color don't keep the previous map's
+    commands.insert_resource(
+        worldspawn
+            .clear_color
+            .map_or_else(ClearColor::default, ClearColor),
+    );
+    if let Some(mut rapier_config) = rapier_config {
+        rapier_config.gravity = Vec3::NEG_Y * worldspawn.gravity;
+    }
+    if let (Some(message), Some(mut windows)) = (&worldspawn.message, windows) {
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_title(message.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+
+    use super::{apply_worldspawn, worldspawn_loader, MapWorldspawn, Worldspawn};
+    use crate::qmap::test_util::properties;
+
+    #[test]
+    fn environment() {
+        let mut app = App::new();
+        app.insert_resource(RapierConfiguration::default())
+            .add_system(worldspawn_loader)
+            .add_system(apply_worldspawn.after(worldspawn_loader));
+
+        app.world.spawn().insert(MapWorldspawn {
+            properties: properties(&[
+                ("classname", "worldspawn"),
+                ("_ambient", "0.25"),
+                ("_ambient_color", "255 0 0"),
+                ("_clear_color", "0 0 0"),
+                ("gravity", "none"),
+                ("message", "Station"),
+                ("sky", "space"),
+            ]),
+        });
+        app.update();
+        app.update();
+
+        let worldspawn = app.world.resource::<Worldspawn>();
+        assert_eq!(Some("Station".to_string()), worldspawn.message);
+        assert_eq!(Some("space".to_string()), worldspawn.sky);
+        // Invalid keys fall back to their default
+        assert_eq!(9.81, worldspawn.gravity);
+
+        let ambient = app.world.resource::<AmbientLight>();
+        assert_eq!(0.25, ambient.brightness);
+        assert_eq!(Color::rgb(1.0, 0.0, 0.0), ambient.color);
+        assert_eq!(Color::BLACK, app.world.resource::<ClearColor>().0);
+        assert_eq!(
+            Vec3::new(0.0, -9.81, 0.0),
+            app.world.resource::<RapierConfiguration>().gravity
+        );
+
+        // Gravity is in world units and can't point up
+        assert_eq!(
+            9.81,
+            Worldspawn::from_properties_or_default(&properties(&[("gravity", "-9.81")])).gravity
+        );
+
+        // Zero-g station
+        app.world.resource_mut::<Worldspawn>().gravity = 0.0;
+        app.update();
+        assert_eq!(
+            Vec3::ZERO,
+            app.world.resource::<RapierConfiguration>().gravity
+        );
+
+        // The next map has no clear color or ambient keys
+        app.world.spawn().insert(MapWorldspawn {
+            properties: properties(&[("classname", "worldspawn")]),
+        });
+        app.update();
+        app.update();
+
+        assert_eq!(
+            ClearColor::default().0,
+            app.world.resource::<ClearColor>().0
+        );
+        assert_eq!(1.0, app.world.resource::<AmbientLight>().brightness);
+    }
+}